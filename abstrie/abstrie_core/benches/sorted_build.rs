@@ -0,0 +1,45 @@
+// Compares `from_sequences` against the sorted-input fast path.
+// Run with `cargo bench -p abstrie_core --bench sorted_build`.
+use std::time::{Duration, Instant};
+
+use abstrie_core::TrieNode;
+
+fn time<F: FnMut()>(runs: u32, mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..runs {
+        f();
+    }
+    start.elapsed() / runs
+}
+
+fn compare(name: &str, mut data: Vec<Vec<u32>>) {
+    data.sort();
+    let sequences: Vec<&[u32]> = data.iter().map(|seq| seq.as_slice()).collect();
+
+    let runs = 5;
+    let unsorted = time(runs, || {
+        std::hint::black_box(TrieNode::from_sequences(&sequences));
+    });
+    let sorted = time(runs, || {
+        std::hint::black_box(TrieNode::from_sorted_sequences(&sequences));
+    });
+
+    println!("{}", name);
+    println!("  from_sequences:        {:?}", unsorted);
+    println!("  from_sorted_sequences: {:?}", sorted);
+    println!("  speedup:               {:.2}x", unsorted.as_secs_f64() / sorted.as_secs_f64());
+}
+
+fn main() {
+    // Heavily repeated long sequences: nothing diverges, so scanning every
+    // member of the group for the common prefix dominates the build
+    compare(
+        "repeated long sequence",
+        (0..20_000u32).map(|_| (0..500).collect()).collect(),
+    );
+    // Mostly divergent input; the common prefix scan is a small share here
+    compare(
+        "short divergent sequences",
+        (0..2_000u32).map(|i| vec![i % 13, i % 17, i % 19, i]).collect(),
+    );
+}
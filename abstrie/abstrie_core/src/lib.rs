@@ -10,12 +10,88 @@ pub mod visualization;
 pub use trie::{
     TrieNode,
     LengthGroupedNode,
+    PatternElement,
+    NodeInfo,
+    TrieStats,
+    CachedStats,
+    BuildError,
+    LazyGroupedNode,
+    ParseError,
+    WordTrieOptions,
+    TrieOp,
+    PatternAutomaton,
+    BuildOptions,
+    Segment,
+    LengthGroupKey,
+    SuffixFactoredTrie,
+    SegmentFormatter,
+    DisplayFormatter,
+    DebugFormatter,
+    AbstractPattern,
+    SpannedTrie,
+    EditError,
+    SequenceIter,
+    NormalizedTrie,
+    QueryElem,
+    DagError,
+    TrieCursor,
+    PrintOptions,
+    SegmentDecision,
+    GroupedPrintOptions,
+    BuildMetrics,
+    SortOrder,
+    GroupReport,
+    ConcurrentTrie,
+    LabeledTrie,
+    CanonicalGroupedDag,
+    KeyError,
+    Edge,
+    RawDagNode,
 };
 
+pub use visualization::{PrefixTree, print_prefix_tree};
+
 // Provide a prelude for convenient imports
 pub mod prelude {
     pub use crate::{
         TrieNode,
         LengthGroupedNode,
+        PatternElement,
+        NodeInfo,
+        TrieStats,
+        CachedStats,
+        BuildError,
+        LazyGroupedNode,
+        ParseError,
+        WordTrieOptions,
+        TrieOp,
+        PatternAutomaton,
+        BuildOptions,
+        Segment,
+        LengthGroupKey,
+        SuffixFactoredTrie,
+        SegmentFormatter,
+        DisplayFormatter,
+        DebugFormatter,
+        AbstractPattern,
+        SpannedTrie,
+        EditError,
+        SequenceIter,
+        NormalizedTrie,
+        QueryElem,
+        DagError,
+        TrieCursor,
+        PrintOptions,
+        SegmentDecision,
+        GroupedPrintOptions,
+        BuildMetrics,
+        SortOrder,
+        GroupReport,
+        ConcurrentTrie,
+        LabeledTrie,
+        CanonicalGroupedDag,
+        KeyError,
+        Edge,
+        RawDagNode,
     };
 }
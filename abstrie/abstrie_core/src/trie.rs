@@ -1,516 +1,3969 @@
-use std::collections::{HashMap, BTreeSet, HashSet};
+use std::collections::{HashMap, BTreeMap, BTreeSet, HashSet};
+use std::cell::{Cell, OnceCell, RefCell};
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
 use std::hash::Hash;
 use std::fmt::{Debug, Display};
+use std::cmp::Ordering;
+
+use crate::visualization::PrefixTree;
+
+// Segment keys are never mutated after construction, so they are stored as
+// boxed slices: no spare capacity, one word smaller than a Vec per key
+pub type Segment<T> = Box<[T]>;
 
 // Generic Trie implementation
 #[derive(Debug, Clone)]
 pub struct TrieNode<T> {
-    children: HashMap<Vec<T>, TrieNode<T>>,
+    children: HashMap<Segment<T>, TrieNode<T>>,
     is_terminal: bool,
+    // Number of times the sequence ending here was inserted (0 for non-terminals)
+    frequency: usize,
+    // Counts for the subtree rooted here (see `cached_stats`)
+    cached: CachedStats,
 }
 
-impl<T> TrieNode<T> 
-where 
-    T: Clone + Eq + Hash + Debug + Display,
-{
-    pub fn new() -> Self {
-        TrieNode {
-            children: HashMap::new(),
-            is_terminal: false,
-        }
-    }
+// Full structural statistics computed by walking the trie
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TrieStats {
+    pub node_count: usize,
+    pub terminal_count: usize,
+    pub max_depth: usize,
+}
 
-    // Build trie with proper segmentation based on branching patterns
-    pub fn from_sequences(sequences: &[&[T]]) -> Self {
-        Self::build_segmented_trie(sequences, 0)
+// Per-subtree counts cached on every node and maintained by `insert`/`remove`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CachedStats {
+    pub node_count: usize,
+    pub terminal_count: usize,
+}
+
+// Settings controlling how `TrieNode` segments its input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildOptions {
+    // Shortest segment shared by several sequences that becomes a merged
+    // segment; shorter shared runs branch one element at a time instead
+    pub min_common_prefix: usize,
+    // Upper bound on the number of nodes (root included); building a larger
+    // trie fails with `BuildError::TooLarge` as soon as the bound is crossed
+    pub max_nodes: usize,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        BuildOptions { min_common_prefix: 1, max_nodes: usize::MAX }
     }
+}
 
-    fn build_segmented_trie(sequences: &[&[T]], start_pos: usize) -> Self {
-        let mut root = TrieNode::new();
-        
-        if sequences.is_empty() {
-            return root;
-        }
+// One step of the segmentation heuristic, as logged by
+// `TrieNode::from_sequences_traced`. `position` is the element offset into
+// the input sequences where the step happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SegmentDecision<T> {
+    // All sequences share `segment`; when they then `diverged`, the node's
+    // segments come from the `Divergent` steps that follow instead
+    CommonPrefix { position: usize, segment: Vec<T>, diverged: bool },
+    // Segment cut where its sequences stop agreeing
+    Divergent { position: usize, segment: Vec<T> },
+    // No shared prefix: segment built for one first-element group
+    NoCommonPrefix { position: usize, segment: Vec<T> },
+    // Shared prefix shorter than `min_common_prefix`: one-element branch
+    SingleElement { position: usize, segment: Vec<T> },
+}
 
-        // Filter sequences that are long enough
-        let valid_sequences: Vec<&[T]> = sequences.iter()
-            .filter(|seq| seq.len() > start_pos)
-            .map(|seq| *seq)
-            .collect();
+// Counters summarizing a build, as returned by `TrieNode::build_with_metrics`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BuildMetrics {
+    // How often each segmentation branch fired (see `SegmentDecision`)
+    pub common_prefix: usize,
+    pub divergent: usize,
+    pub no_common_prefix: usize,
+    pub single_element: usize,
+    // Shared prefixes that had to be split because the sequences diverged
+    pub segment_splits: usize,
+    // Deepest nesting of the recursive node builder (the root call is 1)
+    pub max_recursion_depth: usize,
+}
 
-        if valid_sequences.is_empty() {
-            return root;
-        }
+// Per-build state threaded through the segmenting helpers
+struct BuildContext<'a, T> {
+    options: &'a BuildOptions,
+    nodes: Cell<usize>,
+    // Sequences placed so far, out of `total`, reported through `progress`
+    done: Cell<usize>,
+    total: usize,
+    progress: RefCell<&'a mut dyn FnMut(usize, usize)>,
+    // Decision log, only kept for traced builds
+    trace: Option<RefCell<Vec<SegmentDecision<T>>>>,
+    // Current and deepest nesting of `build_segmented_trie` calls
+    depth: Cell<usize>,
+    max_depth: Cell<usize>,
+    // Input is sorted, so every recursive group is too (see `from_sorted_sequences`)
+    sorted: bool,
+}
 
-        // Check if any sequence ends at this position
-        for seq in sequences {
-            if seq.len() == start_pos {
-                root.is_terminal = true;
-                break;
-            }
+impl<'a, T> BuildContext<'a, T> {
+    fn new(options: &'a BuildOptions, total: usize, progress: &'a mut dyn FnMut(usize, usize)) -> Self {
+        BuildContext {
+            options,
+            nodes: Cell::new(0),
+            done: Cell::new(0),
+            total,
+            progress: RefCell::new(progress),
+            trace: None,
+            depth: Cell::new(0),
+            max_depth: Cell::new(0),
+            sorted: false,
         }
+    }
 
-        // Find the longest common prefix from current position
-        let common_prefix_len = Self::find_longest_common_prefix(&valid_sequences, start_pos);
-        
-        if common_prefix_len > 0 {
-            // If there's a common prefix, create segments based on where sequences diverge
-            Self::build_with_common_prefix(&mut root, &valid_sequences, start_pos, common_prefix_len);
-        } else {
-            // No common prefix, group by first element
-            Self::build_without_common_prefix(&mut root, &valid_sequences, start_pos);
+    // Count one more built node against `options.max_nodes`
+    fn claim_node(&self) -> Result<(), BuildError> {
+        let built = self.nodes.get() + 1;
+        if built > self.options.max_nodes {
+            return Err(BuildError::TooLarge { max_nodes: self.options.max_nodes });
         }
+        self.nodes.set(built);
+        Ok(())
+    }
 
-        root
+    fn report_placed(&self, count: usize) {
+        let done = self.done.get() + count;
+        self.done.set(done);
+        (self.progress.borrow_mut())(done, self.total);
     }
 
-    fn find_longest_common_prefix(sequences: &[&[T]], start_pos: usize) -> usize {
-        if sequences.len() <= 1 {
-            return 0;
+    fn record(&self, decision: impl FnOnce() -> SegmentDecision<T>) {
+        if let Some(trace) = &self.trace {
+            trace.borrow_mut().push(decision());
         }
+    }
+}
 
-        let first_seq = sequences[0];
-        let mut common_len = 0;
-
-        for i in start_pos..first_seq.len() {
-            let element = &first_seq[i];
-            
-            // Check if all sequences have the same element at this position
-            let all_match = sequences.iter().all(|seq| {
-                seq.len() > i && seq[i] == *element
-            });
+// Error raised by the fallible trie builders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    // Segment math indexed outside an input sequence. Every slice in the
+    // builder is checked and reports this instead of panicking; sequences
+    // too short for a position are filtered out first, so jagged input
+    // builds normally rather than hitting it.
+    OutOfRange { start: usize, end: usize, len: usize },
+    // The trie would need more than `max_nodes` nodes
+    TooLarge { max_nodes: usize },
+    // Wildcard expansion would produce more than `limit` sequences
+    TooManyExpansions { limit: usize },
+    // A pattern uses `Any` but the alphabet to expand it over is empty
+    EmptyAlphabet,
+}
 
-            if all_match {
-                common_len = i - start_pos + 1;
-            } else {
-                break;
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::OutOfRange { start, end, len } => write!(
+                f,
+                "segment {}..{} is out of range for a sequence of length {}",
+                start, end, len
+            ),
+            BuildError::TooLarge { max_nodes } => {
+                write!(f, "trie would exceed the limit of {} nodes", max_nodes)
+            }
+            BuildError::TooManyExpansions { limit } => {
+                write!(f, "pattern expansion would exceed the limit of {} sequences", limit)
+            }
+            BuildError::EmptyAlphabet => {
+                write!(f, "cannot expand a wildcard over an empty alphabet")
             }
         }
-
-        common_len
     }
+}
 
-    fn build_with_common_prefix(
-        root: &mut TrieNode<T>, 
-        sequences: &[&[T]], 
-        start_pos: usize, 
-        common_prefix_len: usize
-    ) {
-        // Create the common prefix segment
-        let common_segment: Vec<T> = sequences[0][start_pos..start_pos + common_prefix_len].to_vec();
-        
-        // Group sequences by what comes after the common prefix
-        let mut groups: HashMap<Option<T>, Vec<&[T]>> = HashMap::new();
-        
-        for seq in sequences {
-            let next_pos = start_pos + common_prefix_len;
-            let next_element = if seq.len() > next_pos {
-                Some(seq[next_pos].clone())
-            } else {
-                None
-            };
-            
-            groups.entry(next_element).or_insert_with(Vec::new).push(seq);
-        }
-
-        // If all sequences have the same continuation or no continuation, 
-        // extend the common segment
-        if groups.len() == 1 {
-            let next_pos = start_pos + common_prefix_len;
-            let child = Self::build_segmented_trie(sequences, next_pos);
-            root.children.insert(common_segment, child);
-        } else {
-            // Create segments based on divergence points
-            Self::build_divergent_segments(root, sequences, start_pos);
-        }
-    }
+impl std::error::Error for BuildError {}
 
-    fn build_without_common_prefix(
-        root: &mut TrieNode<T>, 
-        sequences: &[&[T]], 
-        start_pos: usize
-    ) {
-        // Group sequences by their first element at start_pos
-        let mut groups: HashMap<T, Vec<&[T]>> = HashMap::new();
-        
-        for seq in sequences {
-            if seq.len() > start_pos {
-                let first_element = seq[start_pos].clone();
-                groups.entry(first_element).or_insert_with(Vec::new).push(seq);
-            }
-        }
+// Error raised by in-place structural edits addressed by prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditError {
+    // No stored path starts with the prefix
+    PrefixNotFound,
+    // The prefix ends inside a segment; `matched` is the length of the
+    // longest node boundary it reaches
+    NotBoundaryAligned { matched: usize },
+}
 
-        // For each group, find the optimal segment length
-        for (first_element, group_sequences) in groups {
-            let segment = Self::find_optimal_segment(&group_sequences, start_pos);
-            let child = Self::build_segmented_trie(&group_sequences, start_pos + segment.len());
-            root.children.insert(segment, child);
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::PrefixNotFound => write!(f, "prefix does not exist in the trie"),
+            EditError::NotBoundaryAligned { matched } => write!(
+                f,
+                "prefix ends inside a segment (last node boundary at {})",
+                matched
+            ),
         }
     }
+}
 
-    fn build_divergent_segments(
-        root: &mut TrieNode<T>, 
-        sequences: &[&[T]], 
-        start_pos: usize
-    ) {
-        // Group sequences by their prefixes until they diverge
-        let mut groups: HashMap<Vec<T>, Vec<&[T]>> = HashMap::new();
-        
-        for seq in sequences {
-            // Find where this sequence diverges from others
-            let segment = Self::find_segment_until_divergence(seq, sequences, start_pos);
-            groups.entry(segment).or_insert_with(Vec::new).push(seq);
-        }
+impl std::error::Error for EditError {}
 
-        for (segment, group_sequences) in groups {
-            let child = Self::build_segmented_trie(&group_sequences, start_pos + segment.len());
-            root.children.insert(segment, child);
-        }
-    }
+// Element of an abstracted pattern: a fixed element or a wildcard position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatternElement<T> {
+    Exact(T),
+    Any,
+}
 
-    fn find_segment_until_divergence(
-        target_seq: &[T], 
-        all_sequences: &[&[T]], 
-        start_pos: usize
-    ) -> Vec<T> {
-        let mut segment_len = 1;
-        
-        // Start with at least one element
-        if target_seq.len() <= start_pos {
-            return Vec::new();
-        }
+// Element of a `TrieNode::query` pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryElem<T> {
+    // Exactly this element
+    Exact(T),
+    // Any single element
+    Any,
+    // One or more arbitrary elements
+    AnyRepeat,
+}
 
-        // Extend segment until we find a good breakpoint
-        for len in 1..=(target_seq.len() - start_pos) {
-            let potential_segment = target_seq[start_pos..start_pos + len].to_vec();
-            
-            // Check if this is a good breakpoint
-            if Self::is_good_segment_breakpoint(&potential_segment, all_sequences, start_pos) {
-                segment_len = len;
-                break;
-            }
-            segment_len = len;
-        }
+// Rule extracted by `TrieNode::extract_patterns`: fixed elements with
+// wildcard slots where the covered sequences vary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbstractPattern<T> {
+    pub elements: Vec<PatternElement<T>>,
+}
 
-        target_seq[start_pos..start_pos + segment_len].to_vec()
+impl<T> AbstractPattern<T> {
+    // Indices of the `Any` slots
+    pub fn wildcard_positions(&self) -> Vec<usize> {
+        self.elements.iter()
+            .enumerate()
+            .filter(|(_, element)| matches!(element, PatternElement::Any))
+            .map(|(i, _)| i)
+            .collect()
     }
+}
 
-    fn find_optimal_segment(sequences: &[&[T]], start_pos: usize) -> Vec<T> {
-        if sequences.is_empty() {
-            return Vec::new();
+// Elements are concatenated and wildcards shown as `*`, e.g. `log*`
+impl<T: Display> Display for AbstractPattern<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for element in &self.elements {
+            match element {
+                PatternElement::Exact(item) => write!(f, "{}", item)?,
+                PatternElement::Any => write!(f, "*")?,
+            }
         }
+        Ok(())
+    }
+}
 
-        // For now, use a simple heuristic: extend until sequences diverge
-        let first_seq = sequences[0];
-        let mut segment_len = 1;
-
-        for len in 1..=(first_seq.len() - start_pos) {
-            let current_segment = &first_seq[start_pos..start_pos + len];
-            
-            // Check if all sequences in this group share this prefix
-            let all_share_prefix = sequences.iter().all(|seq| {
-                seq.len() >= start_pos + len && 
-                seq[start_pos..start_pos + len] == *current_segment
-            });
+// Edit operation on the stored multiset, as produced by `TrieNode::diff_ops`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrieOp<T> {
+    // Add one occurrence of the sequence
+    Insert(Vec<T>),
+    // Remove the sequence entirely
+    Remove(Vec<T>),
+}
 
-            if all_share_prefix {
-                segment_len = len;
-                
-                // Check if extending further would still be shared
-                if len < first_seq.len() - start_pos {
-                    let extended_len = len + 1;
-                    let still_shared = sequences.iter().all(|seq| {
-                        seq.len() >= start_pos + extended_len && 
-                        seq[start_pos + len] == first_seq[start_pos + len]
-                    });
-                    
-                    if !still_shared {
-                        break; // Good breakpoint found
-                    }
-                }
-            } else {
-                break;
-            }
-        }
+// Node entry of the adjacency-list export produced by `TrieNode::to_edges`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub id: usize,
+    pub is_terminal: bool,
+    pub frequency: usize,
+}
 
-        first_seq[start_pos..start_pos + segment_len].to_vec()
-    }
+// Edge of the adjacency-list export: (parent id, child id, segment)
+pub type Edge<T> = (usize, usize, Vec<T>);
 
-    fn is_good_segment_breakpoint(
-        segment: &[T], 
-        all_sequences: &[&[T]], 
-        start_pos: usize
-    ) -> bool {
-        // A segment is a good breakpoint if:
-        // 1. Some sequences continue after it
-        // 2. Some sequences end at it, or
-        // 3. Sequences diverge after it
+// Node visited by `TrieNode::preorder`, with its (parent id, segment) edge;
+// the root has no edge
+struct PreorderEntry<'a, T> {
+    node: &'a TrieNode<T>,
+    edge: Option<(usize, &'a [T])>,
+}
 
-        let sequences_with_segment: Vec<&[T]> = all_sequences.iter()
-            .filter(|seq| {
-                seq.len() >= start_pos + segment.len() && 
-                seq[start_pos..start_pos + segment.len()] == *segment
-            })
-            .map(|seq| *seq)
-            .collect();
+// Pre-order ids and subtree weights for the tree printer, computed in one
+// walk before any sorting for display
+struct PrintIndex<'a, T> {
+    // Pre-order id of each child, keyed by (parent id, segment)
+    child_ids: HashMap<(usize, &'a [T]), usize>,
+    // Total insertion count stored at or below each node, by id
+    weights: Vec<usize>,
+    id_width: usize,
+}
 
-        if sequences_with_segment.len() <= 1 {
-            return true;
-        }
+// Renders a whole segment as the label of its edge in the tree printers
+pub trait SegmentFormatter<T> {
+    fn format(&self, segment: &[T]) -> String;
+}
 
-        // Check if sequences diverge after this segment
-        let next_pos = start_pos + segment.len();
-        let mut next_elements: HashSet<Option<T>> = HashSet::new();
-        
-        for seq in sequences_with_segment {
-            let next_element = if seq.len() > next_pos {
-                Some(seq[next_pos].clone())
-            } else {
-                None
-            };
-            next_elements.insert(next_element);
-        }
+// Elements rendered with `Display`, space separated (the `print_tree` default)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DisplayFormatter;
 
-        next_elements.len() > 1
+impl<T: Display> SegmentFormatter<T> for DisplayFormatter {
+    fn format(&self, segment: &[T]) -> String {
+        segment.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(" ")
     }
+}
 
-    // Tree visualization method for regular trie
-    pub fn print_tree(&self) {
-        self.print_tree_with_options(" ", ".", false);
-    }
+// Elements rendered with `Debug`, space separated
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DebugFormatter;
 
-    pub fn print_tree_with_options(&self, separator: &str, terminal_char: &str, quote_elements: bool) {
-        println!("Root{}", if self.is_terminal { terminal_char } else { "" });
-        self.print_tree_recursive("", true, separator, terminal_char, quote_elements);
+impl<T: Debug> SegmentFormatter<T> for DebugFormatter {
+    fn format(&self, segment: &[T]) -> String {
+        segment.iter().map(|item| format!("{:?}", item)).collect::<Vec<_>>().join(" ")
     }
+}
 
-    fn print_tree_recursive(&self, prefix: &str, is_last_sibling: bool, separator: &str, terminal_char: &str, quote_elements: bool) {
-        let children: Vec<_> = self.children.iter().collect();
-        
-        for (i, (segment, child)) in children.iter().enumerate() {
-            let is_last = i == children.len() - 1;
-            let branch = if is_last { "└─" } else { "├─" };
-            let child_prefix = if is_last { "  " } else { "│ " };
-            
-            let segment_display = Self::format_segment(segment, separator, quote_elements);
-            println!("{}{} {}{}", prefix, branch, segment_display, 
-                if child.is_terminal { terminal_char } else { "" });
-            
-            child.print_tree_recursive(&format!("{}{}", prefix, child_prefix), is_last, separator, terminal_char, quote_elements);
-        }
+// Trie built by `TrieNode::from_sequences_spanned`, keeping the source
+// offset of every element of each stored sequence
+#[derive(Debug, Clone)]
+pub struct SpannedTrie<T> {
+    trie: TrieNode<T>,
+    spans: HashMap<Vec<T>, Vec<usize>>,
+}
+
+impl<T> SpannedTrie<T>
+where
+    T: Clone + Eq + Hash + Debug + Display,
+{
+    pub fn trie(&self) -> &TrieNode<T> {
+        &self.trie
     }
 
-    fn format_segment(segment: &[T], separator: &str, quote_elements: bool) -> String {
-        segment.iter()
-            .map(|item| {
-                if quote_elements {
-                    format!("{:?}", item)
-                } else {
-                    format!("{}", item)
-                }
-            })
-            .collect::<Vec<_>>()
-            .join(separator)
+    // Per-element offsets of a stored sequence, or None if it is not stored
+    pub fn spans_of(&self, sequence: &[T]) -> Option<Vec<usize>> {
+        self.spans.get(sequence).cloned()
     }
 }
 
-// Generic Length-grouped trie implementation
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct LengthGroupKey<T> 
-where 
-    T: Clone + Eq + Ord + Hash + Debug,
-{
-    length: usize,
-    segments: BTreeSet<Vec<T>>,
+// Trie built by `TrieNode::from_sequences_normalized_with_originals`,
+// keeping the distinct original forms behind each normalized sequence
+#[derive(Debug, Clone)]
+pub struct NormalizedTrie<T> {
+    trie: TrieNode<T>,
+    originals: HashMap<Vec<T>, Vec<Vec<T>>>,
 }
 
-impl<T> LengthGroupKey<T> 
-where 
-    T: Clone + Eq + Ord + Hash + Debug,
+impl<T> NormalizedTrie<T>
+where
+    T: Clone + Eq + Hash + Debug + Display,
 {
-    pub fn new(length: usize, segments: BTreeSet<Vec<T>>) -> Self {
-        LengthGroupKey { length, segments }
+    pub fn trie(&self) -> &TrieNode<T> {
+        &self.trie
+    }
+
+    // Original sequences that normalized to `normalized`, in first-seen
+    // order; empty if it is not stored
+    pub fn original_forms(&self, normalized: &[T]) -> Vec<Vec<T>> {
+        self.originals.get(normalized).cloned().unwrap_or_default()
     }
 }
 
+// Trie built by `TrieNode::from_labeled_sequences`, keeping the labels
+// attached to each stored sequence
 #[derive(Debug, Clone)]
-pub struct LengthGroupedNode<T> 
-where 
-    T: Clone + Eq + Hash + Debug + std::cmp::Ord,
-{
-    children: HashMap<LengthGroupKey<T>, LengthGroupedNode<T>>,
-    is_terminal: bool,
+pub struct LabeledTrie<T, L> {
+    trie: TrieNode<T>,
+    labels: HashMap<Vec<T>, Vec<L>>,
 }
 
-impl<T> LengthGroupedNode<T>
-where 
-    T: Clone + Eq + Ord + Hash + Debug,
+impl<T, L> LabeledTrie<T, L>
+where
+    T: Clone + Eq + Hash + Debug + Display,
+    L: Clone,
 {
-    pub fn new() -> Self {
-        LengthGroupedNode {
-            children: HashMap::new(),
-            is_terminal: false,
-        }
+    pub fn trie(&self) -> &TrieNode<T> {
+        &self.trie
     }
 
-    pub fn from_trie(trie_root: &TrieNode<T>) -> Self {
-        Self::transform_node_recursive(trie_root)
+    // Labels given for `sequence`, in input order (repeats included); empty
+    // if it is not stored
+    pub fn labels_of(&self, sequence: &[T]) -> &[L] {
+        self.labels.get(sequence).map_or(&[], |labels| labels.as_slice())
     }
+}
 
-    fn transform_node_recursive(current_node: &TrieNode<T>) -> Self {
+// Settings for `TrieNode::format_tree_with`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrintOptions {
+    // Placed between the elements of a segment
+    pub separator: String,
+    // Appended to terminal nodes
+    pub terminal_char: String,
+    // Render elements with `Debug` instead of `Display`
+    pub quote_elements: bool,
+    // Instead of the tree, list every stored sequence flattened on its own
+    // line, sorted by the rendered text
+    pub terminals_only: bool,
+    // Start each tree line with its node's id as used by `to_edges` and
+    // `to_dot` (ignored with `terminals_only`)
+    pub show_ids: bool,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        PrintOptions {
+            separator: " ".to_string(),
+            terminal_char: ".".to_string(),
+            quote_elements: false,
+            terminals_only: false,
+            show_ids: false,
+        }
+    }
+}
+
+// Element comparison used to order siblings when printing
+type ElementOrder<'a, T> = &'a dyn Fn(&T, &T) -> Ordering;
+
+// Rendering options shared by the TrieNode tree printers
+struct TreeStyle<'a, T> {
+    separator: &'a str,
+    terminal_char: &'a str,
+    quote_elements: bool,
+    order: Option<ElementOrder<'a, T>>,
+    // Annotate each edge with the share of its parent's terminal weight
+    weighted: bool,
+    // Overrides the separator/quoting rendering of segments when set
+    formatter: Option<&'a dyn SegmentFormatter<T>>,
+    terminals_only: bool,
+    show_ids: bool,
+}
+
+impl<'a, T> TreeStyle<'a, T> {
+    fn new(separator: &'a str, terminal_char: &'a str, quote_elements: bool) -> Self {
+        TreeStyle { separator, terminal_char, quote_elements, order: None, weighted: false, formatter: None, terminals_only: false, show_ids: false }
+    }
+}
+
+impl<T> TrieNode<T> 
+where 
+    T: Clone + Eq + Hash + Debug + Display,
+{
+    pub fn new() -> Self {
+        TrieNode {
+            children: HashMap::new(),
+            is_terminal: false,
+            frequency: 0,
+            cached: CachedStats { node_count: 1, terminal_count: 0 },
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        self.is_terminal
+    }
+
+    pub fn set_terminal(&mut self, value: bool) {
+        if value != self.is_terminal {
+            if value {
+                self.cached.terminal_count += 1;
+            } else {
+                self.cached.terminal_count -= 1;
+            }
+        }
+        self.is_terminal = value;
+        // A terminal always counts at least one insertion, as in every builder
+        if value {
+            self.frequency = self.frequency.max(1);
+        } else {
+            self.frequency = 0;
+        }
+    }
+
+    pub fn num_children(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Read-only view of the child map, keyed by segment.
+    ///
+    /// ```
+    /// use abstrie_core::TrieNode;
+    ///
+    /// let trie = TrieNode::from_words(&["cat", "car", "dog"]);
+    /// let mut labels: Vec<String> = trie.children()
+    ///     .iter()
+    ///     .map(|(segment, child)| {
+    ///         let label: String = segment.iter().collect();
+    ///         format!("{}:{}", label, child.num_children())
+    ///     })
+    ///     .collect();
+    /// labels.sort();
+    /// assert_eq!(labels, vec!["ca:2", "dog:0"]);
+    /// ```
+    pub fn children(&self) -> &HashMap<Segment<T>, TrieNode<T>> {
+        &self.children
+    }
+
+    // Number of times `sequence` was inserted, 0 if it is not stored
+    pub fn frequency(&self, sequence: &[T]) -> usize {
+        self.find_node(sequence)
+            .filter(|node| node.is_terminal)
+            .map_or(0, |node| node.frequency)
+    }
+
+    // Number of distinct stored sequences
+    pub fn count_sequences(&self) -> usize {
+        let own = if self.is_terminal { 1 } else { 0 };
+        own + self.children.values().map(|child| child.count_sequences()).sum::<usize>()
+    }
+
+    // Number of distinct stored sequences starting with `prefix`, which may
+    // end inside a segment
+    pub fn count_with_prefix(&self, prefix: &[T]) -> usize {
+        if prefix.is_empty() {
+            return self.count_sequences();
+        }
+        self.children.iter()
+            .find_map(|(segment, child)| {
+                if prefix.starts_with(segment) {
+                    Some(child.count_with_prefix(&prefix[segment.len()..]))
+                } else if segment.starts_with(prefix) {
+                    Some(child.count_sequences())
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(0)
+    }
+
+    // Every stored sequence starting with `prefix`, which may end inside a
+    // segment
+    pub fn completions(&self, prefix: &[T]) -> Vec<Vec<T>> {
+        let mut node = self;
+        let mut path: Vec<T> = Vec::new();
+        let mut rest = prefix;
+        while !rest.is_empty() {
+            let found = node.children.iter()
+                .find(|(segment, _)| rest.starts_with(segment) || segment.starts_with(rest));
+            match found {
+                Some((segment, child)) => {
+                    path.extend_from_slice(segment);
+                    rest = &rest[segment.len().min(rest.len())..];
+                    node = child;
+                }
+                None => return Vec::new(),
+            }
+        }
+        node.iter_sequences()
+            .map(|suffix| path.iter().cloned().chain(suffix).collect())
+            .collect()
+    }
+
+    // For each stored sequence, its shortest prefix that no other stored
+    // sequence starts with. A sequence that is itself a prefix of another
+    // has no such prefix and maps to the whole sequence.
+    pub fn minimal_unique_prefixes(&self) -> HashMap<Vec<T>, Vec<T>> {
+        self.iter_sequences()
+            .map(|seq| {
+                let len = (1..=seq.len())
+                    .find(|&len| self.count_with_prefix(&seq[..len]) == 1)
+                    .unwrap_or(seq.len());
+                let prefix = seq[..len].to_vec();
+                (seq, prefix)
+            })
+            .collect()
+    }
+
+    pub fn contains(&self, sequence: &[T]) -> bool {
+        self.find_node(sequence).is_some_and(|node| node.is_terminal)
+    }
+
+    // Like `contains`, but follows at most `max_depth` node hops. Returns
+    // `None` when the answer lies deeper than that, so callers can bound work
+    pub fn contains_within_depth(&self, sequence: &[T], max_depth: usize) -> Option<bool> {
+        if sequence.is_empty() {
+            return Some(self.is_terminal);
+        }
+        let mut undetermined = false;
+        for (segment, child) in &self.children {
+            if !sequence.starts_with(segment) {
+                continue;
+            }
+            if max_depth == 0 {
+                undetermined = true;
+                continue;
+            }
+            match child.contains_within_depth(&sequence[segment.len()..], max_depth - 1) {
+                Some(true) => return Some(true),
+                Some(false) => {}
+                None => undetermined = true,
+            }
+        }
+        if undetermined { None } else { Some(false) }
+    }
+
+    // Stored sequences matching `pattern` in full, in unspecified order.
+    // `AnyRepeat` is resolved by backtracking over an element-per-node copy.
+    pub fn query(&self, pattern: &[QueryElem<T>]) -> Vec<Vec<T>> {
+        let mut expanded = self.clone();
+        expanded.resegment(1);
+        let mut matches = HashSet::new();
+        expanded.query_expanded(pattern, &mut Vec::new(), &mut matches);
+        matches.into_iter().collect()
+    }
+
+    fn query_expanded(&self, pattern: &[QueryElem<T>], path: &mut Vec<T>, matches: &mut HashSet<Vec<T>>) {
+        let (first, rest) = match pattern.split_first() {
+            Some(split) => split,
+            None => {
+                if self.is_terminal {
+                    matches.insert(path.clone());
+                }
+                return;
+            }
+        };
+        for (segment, child) in &self.children {
+            let element = &segment[0];
+            if let QueryElem::Exact(wanted) = first {
+                if element != wanted {
+                    continue;
+                }
+            }
+            path.push(element.clone());
+            child.query_expanded(rest, path, matches);
+            if matches!(first, QueryElem::AnyRepeat) {
+                child.query_expanded(pattern, path, matches);
+            }
+            path.pop();
+        }
+    }
+
+    // Like `contains`, but any element of `query` equal to `wildcard`
+    // matches whatever stored element sits at that position
+    pub fn contains_matching(&self, query: &[T], wildcard: &T) -> bool {
+        if query.is_empty() {
+            return self.is_terminal;
+        }
+        self.children.iter().any(|(segment, child)| {
+            segment.len() <= query.len()
+                && segment.iter().zip(query).all(|(stored, wanted)| wanted == wildcard || stored == wanted)
+                && child.contains_matching(&query[segment.len()..], wildcard)
+        })
+    }
+
+    // Node-hop depth of every terminal node (root is depth 0), in ascending order
+    pub fn terminal_depths(&self) -> Vec<usize> {
+        let mut depths = Vec::new();
+        self.collect_terminal_depths(0, &mut depths);
+        depths.sort_unstable();
+        depths
+    }
+
+    fn collect_terminal_depths(&self, depth: usize, depths: &mut Vec<usize>) {
+        if self.is_terminal {
+            depths.push(depth);
+        }
+        for child in self.children.values() {
+            child.collect_terminal_depths(depth + 1, depths);
+        }
+    }
+
+    // True when no stored sequence is a proper prefix of another,
+    // i.e. no terminal node has children
+    pub fn is_prefix_free(&self) -> bool {
+        if self.is_terminal && !self.children.is_empty() {
+            return false;
+        }
+        self.children.values().all(|child| child.is_prefix_free())
+    }
+
+    // Drop every stored sequence that is a proper prefix of another one,
+    // keeping only the longest sequences along each path
+    pub fn make_prefix_free(&mut self) {
+        self.drop_internal_terminals();
+        self.refresh_cached_stats();
+    }
+
+    fn drop_internal_terminals(&mut self) {
+        if !self.children.is_empty() {
+            self.is_terminal = false;
+            self.frequency = 0;
+        }
+        for child in self.children.values_mut() {
+            child.drop_internal_terminals();
+        }
+    }
+
+    // Segment keys used as a child key by more than one node, with the number
+    // of such parent nodes; candidates for sharing in a DAWG-style structure
+    pub fn repeated_segments(&self) -> HashMap<Vec<T>, usize> {
+        let mut counts: HashMap<Vec<T>, usize> = HashMap::new();
+        self.count_segment_uses(&mut counts);
+        counts.retain(|_, count| *count > 1);
+        counts
+    }
+
+    fn count_segment_uses(&self, counts: &mut HashMap<Vec<T>, usize>) {
+        for (segment, child) in &self.children {
+            *counts.entry(segment.to_vec()).or_insert(0) += 1;
+            child.count_segment_uses(counts);
+        }
+    }
+
+    // Flattened prefixes of every node with more than one child, i.e. the
+    // points where stored sequences diverge (the root's prefix is empty)
+    pub fn branching_nodes(&self) -> Vec<Vec<T>> {
+        let mut prefixes = Vec::new();
+        self.collect_branching_nodes(&mut Vec::new(), &mut prefixes);
+        prefixes
+    }
+
+    fn collect_branching_nodes(&self, path: &mut Vec<T>, prefixes: &mut Vec<Vec<T>>) {
+        if self.children.len() > 1 {
+            prefixes.push(path.clone());
+        }
+        for (segment, child) in &self.children {
+            path.extend_from_slice(segment);
+            child.collect_branching_nodes(path, prefixes);
+            path.truncate(path.len() - segment.len());
+        }
+    }
+
+    // Segment keys leading into leaf nodes: the distinctive tails of the
+    // stored sequences, in unspecified order
+    pub fn leaf_segments(&self) -> Vec<Vec<T>> {
+        let mut leaves = Vec::new();
+        self.collect_leaf_segments(&mut leaves);
+        leaves
+    }
+
+    fn collect_leaf_segments(&self, leaves: &mut Vec<Vec<T>>) {
+        for (segment, child) in &self.children {
+            if child.children.is_empty() {
+                leaves.push(segment.to_vec());
+            } else {
+                child.collect_leaf_segments(leaves);
+            }
+        }
+    }
+
+    // Length of the longest segment key anywhere in the trie
+    pub fn longest_segment_len(&self) -> usize {
+        self.children.iter()
+            .map(|(segment, child)| segment.len().max(child.longest_segment_len()))
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Split every segment longer than `max_segment_len` into a chain of
+    // non-terminal nodes holding at most that many elements each
+    pub fn resegment(&mut self, max_segment_len: usize) {
+        assert!(max_segment_len > 0, "max_segment_len must be at least 1");
+
+        let children = std::mem::take(&mut self.children);
+        for (segment, mut child) in children {
+            child.resegment(max_segment_len);
+
+            if segment.len() <= max_segment_len {
+                self.children.insert(segment, child);
+                continue;
+            }
+
+            // Wrap the child bottom-up, one chunk per new node
+            let chunks: Vec<&[T]> = segment.chunks(max_segment_len).collect();
+            let mut node = child;
+            for chunk in chunks[1..].iter().rev() {
+                let mut parent = TrieNode::new();
+                parent.children.insert(Segment::from(*chunk), node);
+                parent.recount();
+                node = parent;
+            }
+            self.children.insert(Segment::from(chunks[0]), node);
+        }
+        self.recount();
+    }
+
+    // All stored sequences, in unspecified order
+    pub fn iter_sequences(&self) -> SequenceIter<T> {
+        let mut sequences = Vec::new();
+        self.collect_sequences(&mut Vec::new(), &mut sequences);
+        SequenceIter { inner: sequences.into_iter() }
+    }
+
+    // Stored sequences accepted by `pred`, in unspecified order
+    pub fn filter_sequences<F: Fn(&[T]) -> bool>(&self, pred: F) -> Vec<Vec<T>> {
+        self.iter_sequences().filter(|seq| pred(seq)).collect()
+    }
+
+    fn collect_sequences(&self, path: &mut Vec<T>, out: &mut Vec<Vec<T>>) {
+        if self.is_terminal {
+            out.push(path.clone());
+        }
+        for (segment, child) in &self.children {
+            path.extend_from_slice(segment);
+            child.collect_sequences(path, out);
+            path.truncate(path.len() - segment.len());
+        }
+    }
+
+    // Stored sequences that pass through the `segment` child of the node at
+    // `prefix`, i.e. the sequences that caused that segment to exist.
+    // Empty when there is no such node or child.
+    pub fn sequences_through_segment(&self, prefix: &[T], segment: &[T]) -> Vec<Vec<T>> {
+        let child = match self.find_node(prefix).and_then(|node| node.children.get(segment)) {
+            Some(child) => child,
+            None => return Vec::new(),
+        };
+        let mut path = prefix.to_vec();
+        path.extend_from_slice(segment);
+        let mut sequences = Vec::new();
+        child.collect_sequences(&mut path, &mut sequences);
+        sequences
+    }
+
+    // Most specific pattern covering every stored sequence under `prefix`.
+    // Positions where those sequences disagree (or some are too short) become `Any`;
+    // returns an empty pattern if `prefix` is not a node boundary or holds no sequences.
+    pub fn abstract_pattern(&self, prefix: &[T]) -> Vec<PatternElement<T>> {
+        let node = match self.find_node(prefix) {
+            Some(node) => node,
+            None => return Vec::new(),
+        };
+        let mut sequences = Vec::new();
+        node.collect_sequences(&mut prefix.to_vec(), &mut sequences);
+
+        let max_len = sequences.iter().map(|seq| seq.len()).max().unwrap_or(0);
+        (0..max_len)
+            .map(|i| {
+                let first = sequences[0].get(i);
+                match first {
+                    Some(element) if sequences.iter().all(|seq| seq.get(i) == Some(element)) => {
+                        PatternElement::Exact(element.clone())
+                    }
+                    _ => PatternElement::Any,
+                }
+            })
+            .collect()
+    }
+
+    // One pattern per branching node whose stored continuations all have the
+    // same length and differ at exactly one position; that position becomes
+    // a wildcard and the path to the node is kept as a fixed prefix
+    pub fn extract_patterns(&self) -> Vec<AbstractPattern<T>> {
+        let mut patterns = Vec::new();
+        self.collect_patterns(&mut Vec::new(), &mut patterns);
+        patterns
+    }
+
+    fn collect_patterns(&self, path: &mut Vec<T>, patterns: &mut Vec<AbstractPattern<T>>) {
+        if self.children.len() >= 2 {
+            let mut continuations = Vec::new();
+            self.collect_sequences(&mut Vec::new(), &mut continuations);
+            // A branching node with no stored sequence below it has no
+            // continuations and yields no pattern
+            let len = continuations.first().map_or(0, |seq| seq.len());
+            if continuations.iter().all(|seq| seq.len() == len) {
+                let varying: Vec<usize> = (0..len)
+                    .filter(|&i| continuations.iter().any(|seq| seq[i] != continuations[0][i]))
+                    .collect();
+                if varying.len() == 1 {
+                    let mut elements: Vec<PatternElement<T>> = path.iter()
+                        .cloned()
+                        .map(PatternElement::Exact)
+                        .collect();
+                    elements.extend(continuations[0].iter().enumerate().map(|(i, element)| {
+                        if i == varying[0] { PatternElement::Any } else { PatternElement::Exact(element.clone()) }
+                    }));
+                    patterns.push(AbstractPattern { elements });
+                }
+            }
+        }
+        for (segment, child) in &self.children {
+            let len = path.len();
+            path.extend_from_slice(segment);
+            child.collect_patterns(path, patterns);
+            path.truncate(len);
+        }
+    }
+
+    // At the node reached by `prefix`, the single element index at which its
+    // child segments differ, provided there are several children, all their
+    // segments have the same length, and every other index agrees
+    pub fn single_variation_axis(&self, prefix: &[T]) -> Option<usize> {
+        let node = self.find_node(prefix)?;
+        let segments: Vec<&Segment<T>> = node.children.keys().collect();
+        let first = segments.first()?;
+        if segments.len() < 2 || segments.iter().any(|segment| segment.len() != first.len()) {
+            return None;
+        }
+        let mut varying = (0..first.len())
+            .filter(|&i| segments.iter().any(|segment| segment[i] != first[i]));
+        match (varying.next(), varying.next()) {
+            (Some(axis), None) => Some(axis),
+            _ => None,
+        }
+    }
+
+    // Flattened path to the deepest node both stored sequences pass through.
+    // Returns None unless both `a` and `b` are stored.
+    pub fn common_ancestor_prefix(&self, a: &[T], b: &[T]) -> Option<Vec<T>> {
+        if !self.contains(a) || !self.contains(b) {
+            return None;
+        }
+
+        let mut node = self;
+        let mut prefix = Vec::new();
+        loop {
+            let (rest_a, rest_b) = (&a[prefix.len()..], &b[prefix.len()..]);
+            let next = node.children.iter().find(|(segment, _)| {
+                !segment.is_empty() && rest_a.starts_with(segment) && rest_b.starts_with(segment)
+            });
+            match next {
+                Some((segment, child)) => {
+                    prefix.extend_from_slice(segment);
+                    node = child;
+                }
+                None => return Some(prefix),
+            }
+        }
+    }
+
+    // Borrow the subtrie rooted at `prefix`, which must end on a segment boundary
+    pub fn get_subtrie(&self, prefix: &[T]) -> Option<&TrieNode<T>> {
+        self.find_node(prefix)
+    }
+
+    // Stateful navigator starting at this node, for step-by-step exploration
+    pub fn cursor(&self) -> TrieCursor<'_, T> {
+        TrieCursor { node: self, path: Vec::new() }
+    }
+
+    // Owned copy of the trie below `prefix`, with the prefix stripped.
+    // `prefix` may end inside a segment; the rest of that segment becomes the root's only child.
+    pub fn extract_subtrie(&self, prefix: &[T]) -> Option<TrieNode<T>> {
+        if prefix.is_empty() {
+            let mut root = self.clone();
+            root.refresh_cached_stats();
+            return Some(root);
+        }
+        for (segment, child) in &self.children {
+            if prefix.starts_with(segment) {
+                if let Some(subtrie) = child.extract_subtrie(&prefix[segment.len()..]) {
+                    return Some(subtrie);
+                }
+            } else if segment.starts_with(prefix) {
+                let mut root = TrieNode::new();
+                root.children.insert(segment[prefix.len()..].into(), child.clone());
+                root.refresh_cached_stats();
+                return Some(root);
+            }
+        }
+        None
+    }
+
+    // Node reached by consuming `sequence` along whole segments
+    fn find_node(&self, sequence: &[T]) -> Option<&TrieNode<T>> {
+        if sequence.is_empty() {
+            return Some(self);
+        }
+        self.children.iter()
+            .filter(|(segment, _)| sequence.starts_with(segment))
+            .find_map(|(segment, child)| child.find_node(&sequence[segment.len()..]))
+    }
+
+    fn find_node_mut(&mut self, sequence: &[T]) -> Option<&mut TrieNode<T>> {
+        if sequence.is_empty() {
+            return Some(self);
+        }
+        self.children.iter_mut()
+            .filter(|(segment, _)| sequence.starts_with(segment))
+            .find_map(|(segment, child)| child.find_node_mut(&sequence[segment.len()..]))
+    }
+
+    // Build trie with proper segmentation based on branching patterns.
+    // Panics on a `BuildError`; use `try_from_sequences` to handle it.
+    pub fn from_sequences(sequences: &[&[T]]) -> Self {
+        Self::try_from_sequences(sequences).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // Like `from_sequences`, but reports out-of-range segment math as a
+    // `BuildError` instead of panicking
+    pub fn try_from_sequences(sequences: &[&[T]]) -> Result<Self, BuildError> {
+        Self::try_from_sequences_with_options(sequences, &BuildOptions::default())
+    }
+
+    // Like `from_sequences`, calling `progress(done, total)` each time some
+    // input sequences have been placed; `done` grows until it equals `total`
+    pub fn from_sequences_with_progress<F: FnMut(usize, usize)>(sequences: &[&[T]], mut progress: F) -> Self {
+        Self::build_with_context(sequences, &BuildOptions::default(), &mut progress)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // Build trie with explicit segmentation settings.
+    // Panics if `options.max_nodes` is exceeded; use
+    // `try_from_sequences_with_options` to handle that as an error.
+    pub fn from_sequences_with_options(sequences: &[&[T]], options: &BuildOptions) -> Self {
+        Self::try_from_sequences_with_options(sequences, options)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // Re-segment the stored sequences (with their frequencies) under new settings
+    pub fn rebuild(&self, options: &BuildOptions) -> Self {
+        let stored: Vec<(Vec<T>, usize)> = self.iter_sequences()
+            .map(|seq| {
+                let frequency = self.frequency(&seq).max(1);
+                (seq, frequency)
+            })
+            .collect();
+        let sequences: Vec<&[T]> = stored.iter()
+            .flat_map(|(seq, frequency)| std::iter::repeat_n(seq.as_slice(), *frequency))
+            .collect();
+        Self::from_sequences_with_options(&sequences, options)
+    }
+
+    pub fn try_from_sequences_with_options(
+        sequences: &[&[T]],
+        options: &BuildOptions,
+    ) -> Result<Self, BuildError> {
+        Self::build_with_context(sequences, options, &mut |_, _| {})
+    }
+
+    fn build_with_context(
+        sequences: &[&[T]],
+        options: &BuildOptions,
+        progress: &mut dyn FnMut(usize, usize),
+    ) -> Result<Self, BuildError> {
+        let ctx = BuildContext::new(options, sequences.len(), progress);
+        Self::run_build(sequences, &ctx)
+    }
+
+    fn run_build(sequences: &[&[T]], ctx: &BuildContext<'_, T>) -> Result<Self, BuildError> {
+        let mut root = Self::build_segmented_trie(sequences, 0, ctx)?;
+        root.refresh_cached_stats();
+        Ok(root)
+    }
+
+    // Snapshots of the trie as the input is consumed in batches of doubling
+    // size (1, 2, 4, ... sequences so far). Each snapshot is a fresh
+    // `from_sequences` build over the prefix consumed so far, so the last one
+    // equals `from_sequences(sequences)`; rebuilding per batch keeps the total
+    // work within a small factor of one full build.
+    pub fn build_incrementally<'a>(sequences: &'a [&'a [T]]) -> impl Iterator<Item = TrieNode<T>> + 'a {
+        let mut consumed = 0;
+        std::iter::from_fn(move || {
+            if consumed == sequences.len() {
+                return None;
+            }
+            consumed = (consumed * 2).clamp(1, sequences.len());
+            Some(Self::from_sequences(&sequences[..consumed]))
+        })
+    }
+
+    // Like `from_sequences`, also counting how the segmentation heuristic
+    // behaved; derived from the `from_sequences_traced` log
+    pub fn build_with_metrics(sequences: &[&[T]]) -> (Self, BuildMetrics) {
+        let (trie, decisions, max_recursion_depth) = Self::run_traced(sequences);
+        let mut metrics = BuildMetrics::default();
+        for decision in &decisions {
+            match decision {
+                SegmentDecision::CommonPrefix { diverged, .. } => {
+                    metrics.common_prefix += 1;
+                    if *diverged {
+                        metrics.segment_splits += 1;
+                    }
+                }
+                SegmentDecision::Divergent { .. } => metrics.divergent += 1,
+                SegmentDecision::NoCommonPrefix { .. } => metrics.no_common_prefix += 1,
+                SegmentDecision::SingleElement { .. } => metrics.single_element += 1,
+            }
+        }
+        metrics.max_recursion_depth = max_recursion_depth;
+        (trie, metrics)
+    }
+
+    // Like `from_sequences`, also returning every segmentation decision in
+    // the order it was made (depth first)
+    pub fn from_sequences_traced(sequences: &[&[T]]) -> (Self, Vec<SegmentDecision<T>>) {
+        let (trie, decisions, _) = Self::run_traced(sequences);
+        (trie, decisions)
+    }
+
+    // Traced default build, also returning the deepest builder nesting
+    fn run_traced(sequences: &[&[T]]) -> (Self, Vec<SegmentDecision<T>>, usize) {
+        let options = BuildOptions::default();
+        let mut no_progress = |_, _| {};
+        let mut ctx = BuildContext::new(&options, sequences.len(), &mut no_progress);
+        ctx.trace = Some(RefCell::new(Vec::new()));
+        let trie = Self::run_build(sequences, &ctx)
+            .unwrap_or_else(|err| panic!("{}", err));
+        let max_depth = ctx.max_depth.get();
+        let decisions = ctx.trace.map(RefCell::into_inner).unwrap_or_default();
+        (trie, decisions, max_depth)
+    }
+
+    // Build trie and report, for each distinct stored sequence, the input
+    // indices that produced it (in ascending order)
+    pub fn from_sequences_indexed(sequences: &[&[T]]) -> (Self, HashMap<Vec<T>, Vec<usize>>) {
+        let mut indices: HashMap<Vec<T>, Vec<usize>> = HashMap::new();
+        for (i, seq) in sequences.iter().enumerate() {
+            indices.entry(seq.to_vec()).or_default().push(i);
+        }
+        (Self::from_sequences(sequences), indices)
+    }
+
+    // Build trie with `sentinel` appended to every sequence, so each stored
+    // sequence ends in an explicit leaf. `sentinel` must not occur in the data.
+    pub fn from_sequences_with_sentinel(sequences: &[&[T]], sentinel: T) -> Self {
+        let terminated: Vec<Vec<T>> = sequences.iter()
+            .map(|seq| {
+                let mut seq = seq.to_vec();
+                seq.push(sentinel.clone());
+                seq
+            })
+            .collect();
+        let slices: Vec<&[T]> = terminated.iter().map(|seq| seq.as_slice()).collect();
+        Self::from_sequences(&slices)
+    }
+
+    // Build from canonicalized elements: `normalize` is applied to every
+    // element first (case folding, unicode normalization, rounding, ...).
+    // The trie stores only the normalized forms, not the originals.
+    pub fn from_sequences_normalized<F: Fn(&T) -> T>(sequences: &[&[T]], normalize: F) -> Self {
+        let normalized: Vec<Vec<T>> = sequences.iter()
+            .map(|seq| seq.iter().map(&normalize).collect())
+            .collect();
+        let slices: Vec<&[T]> = normalized.iter().map(|seq| seq.as_slice()).collect();
+        Self::from_sequences(&slices)
+    }
+
+    // Like `from_sequences_normalized`, but also remembers which original
+    // sequences normalized to each stored one, for `NormalizedTrie::original_forms`
+    pub fn from_sequences_normalized_with_originals<F: Fn(&T) -> T>(
+        sequences: &[&[T]],
+        normalize: F,
+    ) -> NormalizedTrie<T> {
+        let mut originals: HashMap<Vec<T>, Vec<Vec<T>>> = HashMap::new();
+        for seq in sequences {
+            let normalized: Vec<T> = seq.iter().map(&normalize).collect();
+            let forms = originals.entry(normalized).or_default();
+            if !forms.iter().any(|form| form.as_slice() == *seq) {
+                forms.push(seq.to_vec());
+            }
+        }
+        NormalizedTrie { trie: Self::from_sequences_normalized(sequences, normalize), originals }
+    }
+
+    // Build from sequences each tagged with a label. A sequence given more
+    // than once keeps all of its labels, see `LabeledTrie::labels_of`
+    pub fn from_labeled_sequences<L: Clone>(items: &[(&[T], L)]) -> LabeledTrie<T, L> {
+        let mut labels: HashMap<Vec<T>, Vec<L>> = HashMap::new();
+        for (sequence, label) in items {
+            labels.entry(sequence.to_vec()).or_default().push(label.clone());
+        }
+        let sequences: Vec<&[T]> = items.iter().map(|(sequence, _)| *sequence).collect();
+        LabeledTrie { trie: Self::from_sequences(&sequences), labels }
+    }
+
+    // Build from elements tagged with their source offsets. The trie is built
+    // from the bare elements; the offsets of each distinct sequence's first
+    // occurrence are kept alongside for `SpannedTrie::spans_of`
+    pub fn from_sequences_spanned(sequences: &[&[(T, usize)]]) -> SpannedTrie<T> {
+        let mut spans: HashMap<Vec<T>, Vec<usize>> = HashMap::new();
+        let bare: Vec<Vec<T>> = sequences.iter()
+            .map(|seq| {
+                let (elements, offsets): (Vec<T>, Vec<usize>) = seq.iter().cloned().unzip();
+                spans.entry(elements.clone()).or_insert(offsets);
+                elements
+            })
+            .collect();
+        let slices: Vec<&[T]> = bare.iter().map(|seq| seq.as_slice()).collect();
+        SpannedTrie { trie: Self::from_sequences(&slices), spans }
+    }
+
+    // Build trie from pre-aggregated (sequence, count) pairs.
+    // Repeated sequences have their weights summed; zero-weight items are ignored.
+    pub fn from_weighted<I: IntoIterator<Item = (Vec<T>, usize)>>(items: I) -> Self {
+        let mut weights: HashMap<Vec<T>, usize> = HashMap::new();
+        for (sequence, weight) in items {
+            if weight > 0 {
+                *weights.entry(sequence).or_insert(0) += weight;
+            }
+        }
+
+        let sequences: Vec<&[T]> = weights.keys().map(|seq| seq.as_slice()).collect();
+        let mut root = Self::from_sequences(&sequences);
+        for (sequence, weight) in &weights {
+            if let Some(node) = root.find_node_mut(sequence) {
+                node.frequency = *weight;
+            }
+        }
+        root.refresh_cached_stats();
+        root
+    }
+
+    fn build_segmented_trie(
+        sequences: &[&[T]],
+        start_pos: usize,
+        ctx: &BuildContext<'_, T>,
+    ) -> Result<Self, BuildError> {
+        let depth = ctx.depth.get() + 1;
+        ctx.depth.set(depth);
+        ctx.max_depth.set(ctx.max_depth.get().max(depth));
+        let built = Self::build_segmented_node(sequences, start_pos, ctx);
+        ctx.depth.set(depth - 1);
+        built
+    }
+
+    fn build_segmented_node(
+        sequences: &[&[T]],
+        start_pos: usize,
+        ctx: &BuildContext<'_, T>,
+    ) -> Result<Self, BuildError> {
+        ctx.claim_node()?;
+
+        let mut root = TrieNode::new();
+        
+        if sequences.is_empty() {
+            return Ok(root);
+        }
+
+        // Check if any sequence ends at this position
+        root.frequency = sequences.iter().filter(|seq| seq.len() == start_pos).count();
+        root.is_terminal = root.frequency > 0;
+        if root.is_terminal {
+            ctx.report_placed(root.frequency);
+        }
+
+        // Filter sequences that are long enough
+        let valid_sequences: Vec<&[T]> = sequences.iter()
+            .filter(|seq| seq.len() > start_pos)
+            .map(|seq| *seq)
+            .collect();
+
+        if valid_sequences.is_empty() {
+            return Ok(root);
+        }
+
+        // Find the longest common prefix from current position
+        let common_prefix_len = if ctx.sorted {
+            Self::sorted_common_prefix(&valid_sequences, start_pos)
+        } else {
+            Self::find_longest_common_prefix(&valid_sequences, start_pos)
+        };
+        
+        if common_prefix_len > 0 && common_prefix_len < ctx.options.min_common_prefix {
+            // Shared prefix too short to become a segment: branch one element at a time
+            Self::build_single_element_branches(&mut root, &valid_sequences, start_pos, ctx)?;
+        } else if common_prefix_len > 0 {
+            // If there's a common prefix, create segments based on where sequences diverge
+            Self::build_with_common_prefix(&mut root, &valid_sequences, start_pos, common_prefix_len, ctx)?;
+        } else {
+            // No common prefix, group by first element
+            Self::build_without_common_prefix(&mut root, &valid_sequences, start_pos, ctx)?;
+        }
+
+        Ok(root)
+    }
+
+    // Checked `seq[start..end]`, reporting out-of-range access instead of panicking
+    fn segment_at(seq: &[T], start: usize, end: usize) -> Result<&[T], BuildError> {
+        seq.get(start..end).ok_or(BuildError::OutOfRange { start, end, len: seq.len() })
+    }
+
+    fn find_longest_common_prefix(sequences: &[&[T]], start_pos: usize) -> usize {
+        if sequences.len() <= 1 {
+            return 0;
+        }
+
+        let first_seq = sequences[0];
+        let mut common_len = 0;
+
+        for (i, element) in first_seq.iter().enumerate().skip(start_pos) {
+            // Check if all sequences have the same element at this position
+            let all_match = sequences.iter().all(|seq| seq.get(i) == Some(element));
+
+            if all_match {
+                common_len = i - start_pos + 1;
+            } else {
+                break;
+            }
+        }
+
+        common_len
+    }
+
+    // For a sorted group the common prefix of all members is the common
+    // prefix of the first and last one
+    fn sorted_common_prefix(sequences: &[&[T]], start_pos: usize) -> usize {
+        match (sequences.first(), sequences.last()) {
+            (Some(first), Some(last)) if sequences.len() > 1 => first.iter()
+                .zip(last.iter())
+                .skip(start_pos)
+                .take_while(|(a, b)| a == b)
+                .count(),
+            _ => 0,
+        }
+    }
+
+    fn build_with_common_prefix(
+        root: &mut TrieNode<T>, 
+        sequences: &[&[T]], 
+        start_pos: usize, 
+        common_prefix_len: usize,
+        ctx: &BuildContext<'_, T>,
+    ) -> Result<(), BuildError> {
+        // Create the common prefix segment
+        let next_pos = start_pos + common_prefix_len;
+        let common_segment: Vec<T> = Self::segment_at(sequences[0], start_pos, next_pos)?.to_vec();
+        
+        // Group sequences by what comes after the common prefix
+        let mut groups: HashMap<Option<T>, Vec<&[T]>> = HashMap::new();
+        
+        for seq in sequences {
+            let next_element = seq.get(next_pos).cloned();
+            groups.entry(next_element).or_insert_with(Vec::new).push(seq);
+        }
+
+        // If all sequences have the same continuation or no continuation, 
+        // extend the common segment
+        ctx.record(|| SegmentDecision::CommonPrefix {
+            position: start_pos,
+            segment: common_segment.clone(),
+            diverged: groups.len() > 1,
+        });
+        if groups.len() == 1 && groups.contains_key(&None) {
+            // Every sequence ends with the common prefix: a terminal leaf
+            // holding all of them, with nothing left to segment
+            ctx.claim_node()?;
+            let mut leaf = TrieNode::new();
+            leaf.frequency = sequences.len();
+            leaf.is_terminal = true;
+            ctx.report_placed(sequences.len());
+            root.children.insert(common_segment.into(), leaf);
+        } else if groups.len() == 1 {
+            let child = Self::build_segmented_trie(sequences, next_pos, ctx)?;
+            root.children.insert(common_segment.into(), child);
+        } else {
+            // Create segments based on divergence points
+            Self::build_divergent_segments(root, sequences, start_pos, ctx)?;
+        }
+        Ok(())
+    }
+
+    fn build_without_common_prefix(
+        root: &mut TrieNode<T>, 
+        sequences: &[&[T]], 
+        start_pos: usize,
+        ctx: &BuildContext<'_, T>,
+    ) -> Result<(), BuildError> {
+        // Group sequences by their first element at start_pos
+        let mut groups: HashMap<T, Vec<&[T]>> = HashMap::new();
+        
+        for seq in sequences {
+            if let Some(first_element) = seq.get(start_pos) {
+                groups.entry(first_element.clone()).or_insert_with(Vec::new).push(seq);
+            }
+        }
+
+        // For each group, find the optimal segment length
+        for group_sequences in groups.into_values() {
+            let mut segment = Self::find_optimal_segment(&group_sequences, start_pos)?;
+            // A segment shared by several sequences is a merged segment too,
+            // so it must reach `min_common_prefix` like a common prefix does
+            if group_sequences.len() > 1 && segment.len() < ctx.options.min_common_prefix {
+                segment.truncate(1);
+            }
+            ctx.record(|| SegmentDecision::NoCommonPrefix { position: start_pos, segment: segment.clone() });
+            let child = Self::build_segmented_trie(&group_sequences, start_pos + segment.len(), ctx)?;
+            root.children.insert(segment.into(), child);
+        }
+        Ok(())
+    }
+
+    fn build_single_element_branches(
+        root: &mut TrieNode<T>,
+        sequences: &[&[T]],
+        start_pos: usize,
+        ctx: &BuildContext<'_, T>,
+    ) -> Result<(), BuildError> {
+        let mut groups: HashMap<T, Vec<&[T]>> = HashMap::new();
+
+        for seq in sequences {
+            if let Some(element) = seq.get(start_pos) {
+                groups.entry(element.clone()).or_default().push(seq);
+            }
+        }
+
+        for (element, group_sequences) in groups {
+            ctx.record(|| SegmentDecision::SingleElement { position: start_pos, segment: vec![element.clone()] });
+            let child = Self::build_segmented_trie(&group_sequences, start_pos + 1, ctx)?;
+            root.children.insert(vec![element].into(), child);
+        }
+        Ok(())
+    }
+
+    fn build_divergent_segments(
+        root: &mut TrieNode<T>, 
+        sequences: &[&[T]], 
+        start_pos: usize,
+        ctx: &BuildContext<'_, T>,
+    ) -> Result<(), BuildError> {
+        // Group sequences by their prefixes until they diverge
+        let mut groups: HashMap<Vec<T>, Vec<&[T]>> = HashMap::new();
+        
+        for seq in sequences {
+            // Find where this sequence diverges from others
+            let segment = Self::find_segment_until_divergence(seq, sequences, start_pos)?;
+            groups.entry(segment).or_insert_with(Vec::new).push(seq);
+        }
+
+        for (segment, group_sequences) in groups {
+            ctx.record(|| SegmentDecision::Divergent { position: start_pos, segment: segment.clone() });
+            let child = Self::build_segmented_trie(&group_sequences, start_pos + segment.len(), ctx)?;
+            root.children.insert(segment.into(), child);
+        }
+        Ok(())
+    }
+
+    fn find_segment_until_divergence(
+        target_seq: &[T], 
+        all_sequences: &[&[T]], 
+        start_pos: usize
+    ) -> Result<Vec<T>, BuildError> {
+        let mut segment_len = 1;
+        
+        // Start with at least one element
+        if target_seq.len() <= start_pos {
+            return Ok(Vec::new());
+        }
+
+        // Extend segment until we find a good breakpoint
+        for len in 1..=(target_seq.len() - start_pos) {
+            let potential_segment = Self::segment_at(target_seq, start_pos, start_pos + len)?;
+            
+            // Check if this is a good breakpoint
+            if Self::is_good_segment_breakpoint(potential_segment, all_sequences, start_pos) {
+                segment_len = len;
+                break;
+            }
+            segment_len = len;
+        }
+
+        Ok(Self::segment_at(target_seq, start_pos, start_pos + segment_len)?.to_vec())
+    }
+
+    fn find_optimal_segment(sequences: &[&[T]], start_pos: usize) -> Result<Vec<T>, BuildError> {
+        if sequences.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // For now, use a simple heuristic: extend until sequences diverge
+        let first_seq = sequences[0];
+        let remaining = first_seq.len().checked_sub(start_pos).ok_or(BuildError::OutOfRange {
+            start: start_pos,
+            end: start_pos,
+            len: first_seq.len(),
+        })?;
+        let mut segment_len = 1;
+
+        for len in 1..=remaining {
+            let current_segment = Self::segment_at(first_seq, start_pos, start_pos + len)?;
+            
+            // Check if all sequences in this group share this prefix
+            let all_share_prefix = sequences.iter().all(|seq| {
+                seq.get(start_pos..start_pos + len) == Some(current_segment)
+            });
+
+            if all_share_prefix {
+                segment_len = len;
+                
+                // Check if extending further would still be shared
+                if len < remaining {
+                    let next = first_seq.get(start_pos + len);
+                    let still_shared = sequences.iter().all(|seq| seq.get(start_pos + len) == next);
+                    
+                    if !still_shared {
+                        break; // Good breakpoint found
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+
+        Ok(Self::segment_at(first_seq, start_pos, start_pos + segment_len)?.to_vec())
+    }
+
+    fn is_good_segment_breakpoint(
+        segment: &[T], 
+        all_sequences: &[&[T]], 
+        start_pos: usize
+    ) -> bool {
+        // A segment is a good breakpoint if:
+        // 1. Some sequences continue after it
+        // 2. Some sequences end at it, or
+        // 3. Sequences diverge after it
+
+        let sequences_with_segment: Vec<&[T]> = all_sequences.iter()
+            .filter(|seq| seq.get(start_pos..start_pos + segment.len()) == Some(segment))
+            .map(|seq| *seq)
+            .collect();
+
+        if sequences_with_segment.len() <= 1 {
+            return true;
+        }
+
+        // Check if sequences diverge after this segment
+        let next_pos = start_pos + segment.len();
+        let mut next_elements: HashSet<Option<T>> = HashSet::new();
+        
+        for seq in sequences_with_segment {
+            next_elements.insert(seq.get(next_pos).cloned());
+        }
+
+        next_elements.len() > 1
+    }
+
+    // Add one occurrence of `sequence`, splitting an existing segment where the
+    // sequence diverges from it. Returns true if the sequence was not stored before.
+    pub fn insert(&mut self, sequence: &[T]) -> bool {
+        let (_, new_terminal) = self.insert_recursive(sequence);
+        new_terminal
+    }
+
+    // Returns (nodes created, whether a new terminal was created), after
+    // adding both to the cached counts of every node on the path
+    fn insert_recursive(&mut self, sequence: &[T]) -> (usize, bool) {
+        let (new_nodes, new_terminal) = self.insert_below(sequence);
+        self.cached.node_count += new_nodes;
+        if new_terminal {
+            self.cached.terminal_count += 1;
+        }
+        (new_nodes, new_terminal)
+    }
+
+    fn insert_below(&mut self, sequence: &[T]) -> (usize, bool) {
+        if sequence.is_empty() {
+            let was_terminal = self.is_terminal;
+            self.is_terminal = true;
+            self.frequency += 1;
+            return (0, !was_terminal);
+        }
+
+        // Child whose segment shares the longest non-empty prefix with the sequence
+        let best = self.children.keys()
+            .map(|segment| (Self::shared_prefix_len(segment, sequence), segment))
+            .filter(|(shared, _)| *shared > 0)
+            .max_by_key(|(shared, _)| *shared)
+            .map(|(shared, segment)| (shared, segment.clone()));
+
+        match best {
+            Some((shared, segment)) if shared == segment.len() => {
+                let child = self.children.get_mut(&segment).unwrap();
+                child.insert_recursive(&sequence[shared..])
+            }
+            Some((shared, segment)) => {
+                let old_child = self.children.remove(&segment).unwrap();
+                let mut middle = TrieNode::new();
+                middle.children.insert(segment[shared..].into(), old_child);
+                middle.recount();
+                let (new_nodes, new_terminal) = middle.insert_recursive(&sequence[shared..]);
+                self.children.insert(segment[..shared].into(), middle);
+                (new_nodes + 1, new_terminal)
+            }
+            None => {
+                let mut leaf = TrieNode::new();
+                leaf.set_terminal(true);
+                leaf.frequency = 1;
+                self.children.insert(sequence.into(), leaf);
+                (1, true)
+            }
+        }
+    }
+
+    // Remove `sequence` entirely, pruning emptied nodes and re-joining segments
+    // left with a single non-terminal link. Returns true if it was stored.
+    pub fn remove(&mut self, sequence: &[T]) -> bool {
+        self.remove_recursive(sequence).is_some()
+    }
+
+    // Returns the number of nodes removed, or None if the sequence was not
+    // stored (in which case nothing is changed). Like `find_node`, every child
+    // whose segment prefixes the sequence is tried. The cached counts of every
+    // node on the path are updated.
+    fn remove_recursive(&mut self, sequence: &[T]) -> Option<usize> {
+        if sequence.is_empty() {
+            if !self.is_terminal {
+                return None;
+            }
+            self.set_terminal(false);
+            return Some(0);
+        }
+
+        let candidates: Vec<Segment<T>> = self.children.keys()
+            .filter(|segment| sequence.starts_with(segment))
+            .cloned()
+            .collect();
+        for segment in candidates {
+            let child = self.children.get_mut(&segment).unwrap();
+            if let Some(removed_below) = child.remove_recursive(&sequence[segment.len()..]) {
+                let removed = removed_below + self.tidy_child(segment);
+                self.cached.node_count -= removed;
+                self.cached.terminal_count -= 1;
+                return Some(removed);
+            }
+        }
+        None
+    }
+
+    // After an edit below the child at `segment`: drop it if it became an
+    // empty non-terminal, or join it with its only child. Returns the
+    // number of nodes that disappeared.
+    fn tidy_child(&mut self, segment: Segment<T>) -> usize {
+        let child = &self.children[&segment];
+        if !child.is_terminal && child.children.is_empty() {
+            self.children.remove(&segment);
+            1
+        } else if !child.is_terminal && child.children.len() == 1 {
+            let mut child = self.children.remove(&segment).unwrap();
+            let (tail, grandchild) = child.children.drain().next().unwrap();
+            let mut joined = segment.into_vec();
+            joined.extend_from_slice(&tail);
+            self.children.insert(joined.into(), grandchild);
+            1
+        } else {
+            0
+        }
+    }
+
+    fn shared_prefix_len(a: &[T], b: &[T]) -> usize {
+        a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count()
+    }
+
+    // Insert/remove operations that turn the stored multiset of `self` into
+    // that of `other`: removals first, then one `Insert` per missing occurrence
+    pub fn diff_ops(&self, other: &TrieNode<T>) -> Vec<TrieOp<T>> {
+        let mut removals = Vec::new();
+        let mut inserts = Vec::new();
+
+        for seq in self.iter_sequences() {
+            let (have, want) = (self.frequency(&seq), other.frequency(&seq));
+            if want < have {
+                removals.push(TrieOp::Remove(seq.clone()));
+                inserts.extend(std::iter::repeat_n(TrieOp::Insert(seq), want));
+            } else if want > have {
+                inserts.extend(std::iter::repeat_n(TrieOp::Insert(seq), want - have));
+            }
+        }
+        for seq in other.iter_sequences() {
+            if !self.contains(&seq) {
+                let want = other.frequency(&seq);
+                inserts.extend(std::iter::repeat_n(TrieOp::Insert(seq), want));
+            }
+        }
+
+        removals.extend(inserts);
+        removals
+    }
+
+    // Apply operations produced by `diff_ops`
+    pub fn apply_ops(&mut self, ops: &[TrieOp<T>]) {
+        for op in ops {
+            match op {
+                TrieOp::Insert(seq) => {
+                    self.insert(seq);
+                }
+                TrieOp::Remove(seq) => {
+                    self.remove(seq);
+                }
+            }
+        }
+    }
+
+    // Aho-Corasick automaton over the stored sequences, for finding all of them
+    // inside a longer text in a single pass
+    pub fn build_automaton(&self) -> PatternAutomaton<T> {
+        PatternAutomaton::from_trie(self)
+    }
+
+    // Fresh trie of the sequences stored in both tries, each with the
+    // smaller of its two insertion counts
+    pub fn intersect(&self, other: &TrieNode<T>) -> TrieNode<T> {
+        let common: Vec<(Vec<T>, usize)> = self.iter_sequences()
+            .filter(|seq| other.contains(seq))
+            .map(|seq| {
+                let frequency = self.frequency(&seq).min(other.frequency(&seq)).max(1);
+                (seq, frequency)
+            })
+            .collect();
+        Self::from_weighted(common)
+    }
+
+    // Fresh trie of the sequences stored in either trie, with their
+    // insertion counts summed; the non-mutating counterpart of `merge`.
+    // Like `intersect` and `difference` it is rebuilt with the default
+    // segmentation, so inputs built with other `BuildOptions` come back
+    // re-segmented.
+    pub fn union(&self, other: &TrieNode<T>) -> TrieNode<T> {
+        let weighted = |trie: &TrieNode<T>| -> Vec<(Vec<T>, usize)> {
+            trie.iter_sequences()
+                .map(|seq| {
+                    let frequency = trie.frequency(&seq).max(1);
+                    (seq, frequency)
+                })
+                .collect()
+        };
+        let mut combined = weighted(self);
+        combined.extend(weighted(other));
+        Self::from_weighted(combined)
+    }
+
+    // Fresh trie of the sequences stored in `self` but not in `other`,
+    // keeping their insertion counts from `self`
+    pub fn difference(&self, other: &TrieNode<T>) -> TrieNode<T> {
+        let remaining: Vec<(Vec<T>, usize)> = self.iter_sequences()
+            .filter(|seq| !other.contains(seq))
+            .map(|seq| {
+                let frequency = self.frequency(&seq).max(1);
+                (seq, frequency)
+            })
+            .collect();
+        Self::from_weighted(remaining)
+    }
+
+    // |intersection| / |union| of the two stored sequence sets, ignoring
+    // insertion counts. Two empty tries are identical and score 1.0.
+    pub fn jaccard(&self, other: &TrieNode<T>) -> f64 {
+        let union = self.union(other).count_sequences();
+        if union == 0 {
+            return 1.0;
+        }
+        self.intersect(other).count_sequences() as f64 / union as f64
+    }
+
+    // Split into (sequences shorter than `threshold`, the rest), each rebuilt
+    // from scratch with its insertion counts
+    pub fn partition_by_length(&self, threshold: usize) -> (TrieNode<T>, TrieNode<T>) {
+        let (short, long): (Vec<_>, Vec<_>) = self.iter_sequences()
+            .map(|seq| {
+                let frequency = self.frequency(&seq).max(1);
+                (seq, frequency)
+            })
+            .partition(|(seq, _)| seq.len() < threshold);
+        (Self::from_weighted(short), Self::from_weighted(long))
+    }
+
+    // Keep only stored sequences whose flattened form satisfies `keep`,
+    // pruning subtrees left without terminals
+    pub fn retain<F: Fn(&[T]) -> bool>(&mut self, keep: F) {
+        self.retain_where(&mut Vec::new(), &keep);
+        self.refresh_cached_stats();
+    }
+
+    // Keep only stored sequences whose total element count satisfies `keep`,
+    // pruning subtrees left without terminals
+    pub fn retain_by_total_length<F: Fn(usize) -> bool>(&mut self, keep: F) {
+        self.retain(|seq| keep(seq.len()));
+    }
+
+    // Unmark terminals whose full sequence fails `keep`. Only children that
+    // lost a terminal are tidied: dropped if emptied, or re-joined when left
+    // with a single non-terminal continuation. Returns whether anything in
+    // this subtree was unmarked.
+    fn retain_where(&mut self, path: &mut Vec<T>, keep: &dyn Fn(&[T]) -> bool) -> bool {
+        let mut removed = false;
+        if self.is_terminal && !keep(path) {
+            self.is_terminal = false;
+            self.frequency = 0;
+            removed = true;
+        }
+
+        let children = std::mem::take(&mut self.children);
+        for (segment, mut child) in children {
+            path.extend_from_slice(&segment);
+            let child_removed = child.retain_where(path, keep);
+            path.truncate(path.len() - segment.len());
+            removed |= child_removed;
+
+            if child_removed && !child.is_terminal && child.children.len() == 1 {
+                let (tail, grandchild) = child.children.drain().next().unwrap();
+                let mut joined = segment.into_vec();
+                joined.extend_from_slice(&tail);
+                self.adopt_child(joined.into(), grandchild);
+            } else if child.is_terminal || !child.children.is_empty() {
+                self.adopt_child(segment, child);
+            }
+        }
+        removed
+    }
+
+    // Insert `child` under `segment`, merging it into a sibling that already
+    // has that key (possible after `merge` left overlapping siblings)
+    fn adopt_child(&mut self, segment: Segment<T>, child: TrieNode<T>) {
+        match self.children.get_mut(&segment) {
+            Some(existing) => existing.merge_structural(child),
+            None => {
+                self.children.insert(segment, child);
+            }
+        }
+    }
+
+    // Swap the children and terminal state of the node at `prefix` for those
+    // of `new_subtrie`. The prefix must end exactly on a node boundary.
+    // Like the other edits, a node left empty or with a single non-terminal
+    // continuation is pruned or re-joined with its parent's link.
+    pub fn replace_subtrie(&mut self, prefix: &[T], new_subtrie: TrieNode<T>) -> Result<(), EditError> {
+        if prefix.is_empty() {
+            self.swap_contents(new_subtrie);
+        } else {
+            self.replace_subtrie_recursive(prefix, 0, new_subtrie)?;
+        }
+        self.refresh_cached_stats();
+        Ok(())
+    }
+
+    fn swap_contents(&mut self, new_subtrie: TrieNode<T>) {
+        self.children = new_subtrie.children;
+        self.is_terminal = new_subtrie.is_terminal;
+        self.frequency = new_subtrie.frequency;
+    }
+
+    // Returns the number of nodes pruned or joined away at this level, so
+    // ancestors are only tidied when the shape below them changed
+    fn replace_subtrie_recursive(
+        &mut self,
+        rest: &[T],
+        matched: usize,
+        new_subtrie: TrieNode<T>,
+    ) -> Result<usize, EditError> {
+        if self.children.keys().any(|segment| segment.len() > rest.len() && segment.starts_with(rest)) {
+            return Err(EditError::NotBoundaryAligned { matched });
+        }
+        let segment = self.children.keys()
+            .find(|segment| rest.starts_with(segment))
+            .ok_or(EditError::PrefixNotFound)?
+            .clone();
+        let child = self.children.get_mut(&segment).unwrap();
+        if segment.len() == rest.len() {
+            child.swap_contents(new_subtrie);
+            return Ok(self.tidy_child(segment));
+        }
+        let removed_below = child.replace_subtrie_recursive(&rest[segment.len()..], matched + segment.len(), new_subtrie)?;
+        Ok(if removed_below > 0 { self.tidy_child(segment) } else { 0 })
+    }
+
+    // Detach the subtree at `prefix` and return it as its own trie; the
+    // counterpart of `replace_subtrie`. Returns None unless `prefix` ends
+    // exactly on a node boundary. An empty prefix takes the whole trie.
+    pub fn take_subtrie(&mut self, prefix: &[T]) -> Option<TrieNode<T>> {
+        let mut taken = if prefix.is_empty() {
+            std::mem::replace(self, TrieNode::new())
+        } else {
+            self.take_subtrie_recursive(prefix)?
+        };
+        self.refresh_cached_stats();
+        taken.refresh_cached_stats();
+        Some(taken)
+    }
+
+    fn take_subtrie_recursive(&mut self, rest: &[T]) -> Option<TrieNode<T>> {
+        let segment = self.children.keys()
+            .find(|segment| rest.starts_with(segment))?
+            .clone();
+        if segment.len() == rest.len() {
+            return self.children.remove(&segment);
+        }
+        let child = self.children.get_mut(&segment).unwrap();
+        let taken = child.take_subtrie_recursive(&rest[segment.len()..])?;
+        self.tidy_child(segment);
+        Some(taken)
+    }
+
+    // Add every sequence of `other` into this trie, summing frequencies.
+    // Children are matched by identical segment keys only, so tries
+    // segmented differently can end up with overlapping sibling branches;
+    // use `merge_normalized` for those.
+    pub fn merge(&mut self, other: &TrieNode<T>) {
+        self.merge_structural(other.clone());
+        self.refresh_cached_stats();
+    }
+
+    // Merge tries that may segment the same sequences differently: both are
+    // split into single-element segments, merged, then single-child chains
+    // are joined back into longer segments
+    pub fn merge_normalized(&mut self, other: &TrieNode<T>) {
+        let mut other = other.clone();
+        other.resegment(1);
+        self.resegment(1);
+        self.merge_structural(other);
+        self.compact();
+    }
+
+    fn merge_structural(&mut self, other: TrieNode<T>) {
+        self.is_terminal |= other.is_terminal;
+        self.frequency += other.frequency;
+        for (segment, other_child) in other.children {
+            match self.children.get_mut(&segment) {
+                Some(child) => child.merge_structural(other_child),
+                None => {
+                    self.children.insert(segment, other_child);
+                }
+            }
+        }
+    }
+
+    // Merge every non-terminal single-child chain into one segment, e.g.
+    // `a -> b -> c` becomes `a -> bc`; stored sequences are unchanged
+    pub fn compact(&mut self) {
+        self.join_unary_chains();
+        self.refresh_cached_stats();
+    }
+
+    fn join_unary_chains(&mut self) {
+        let children = std::mem::take(&mut self.children);
+        for (segment, mut child) in children {
+            child.join_unary_chains();
+            if !child.is_terminal && child.children.len() == 1 {
+                let (tail, grandchild) = child.children.drain().next().unwrap();
+                let mut joined = segment.into_vec();
+                joined.extend_from_slice(&tail);
+                self.children.insert(joined.into(), grandchild);
+            } else {
+                self.children.insert(segment, child);
+            }
+        }
+    }
+
+    // Walk the whole trie and compute its statistics
+    pub fn stats(&self) -> TrieStats {
+        let mut stats = TrieStats::default();
+        self.collect_stats(0, &mut stats);
+        stats
+    }
+
+    fn collect_stats(&self, depth: usize, stats: &mut TrieStats) {
+        stats.node_count += 1;
+        if self.is_terminal {
+            stats.terminal_count += 1;
+        }
+        stats.max_depth = stats.max_depth.max(depth);
+        for child in self.children.values() {
+            child.collect_stats(depth + 1, stats);
+        }
+    }
+
+    // Node and terminal counts of the subtree rooted at this node, kept on
+    // every node by the constructors and all public mutations
+    pub fn cached_stats(&self) -> CachedStats {
+        self.cached
+    }
+
+    // Recompute the cached counts of every node in the subtree
+    pub fn refresh_cached_stats(&mut self) {
+        for child in self.children.values_mut() {
+            child.refresh_cached_stats();
+        }
+        self.recount();
+    }
+
+    // Recompute this node's cached counts from its children's caches
+    fn recount(&mut self) {
+        let mut cached = CachedStats { node_count: 1, terminal_count: usize::from(self.is_terminal) };
+        for child in self.children.values() {
+            cached.node_count += child.cached.node_count;
+            cached.terminal_count += child.cached.terminal_count;
+        }
+        self.cached = cached;
+    }
+
+    // For each prefix length from 1 up to the longest stored sequence, the
+    // number of distinct stored sequences at least that long
+    pub fn prefix_counts(&self) -> BTreeMap<usize, usize> {
+        let mut lengths = Vec::new();
+        self.collect_terminal_lengths(0, &mut lengths);
+        let max_len = lengths.iter().copied().max().unwrap_or(0);
+        (1..=max_len)
+            .map(|len| (len, lengths.iter().filter(|&&l| l >= len).count()))
+            .collect()
+    }
+
+    // Mean element length of the distinct stored sequences (insertion
+    // counts are ignored); 0.0 for an empty trie
+    pub fn average_sequence_length(&self) -> f64 {
+        let mut lengths = Vec::new();
+        self.collect_terminal_lengths(0, &mut lengths);
+        if lengths.is_empty() {
+            return 0.0;
+        }
+        lengths.iter().sum::<usize>() as f64 / lengths.len() as f64
+    }
+
+    fn collect_terminal_lengths(&self, len: usize, lengths: &mut Vec<usize>) {
+        if self.is_terminal {
+            lengths.push(len);
+        }
+        for (segment, child) in &self.children {
+            child.collect_terminal_lengths(len + segment.len(), lengths);
+        }
+    }
+
+    // Rough estimate, in bytes, of the heap owned by the trie: each child map's
+    // bucket storage (entries plus one control byte per bucket, by capacity)
+    // and each segment key's elements. Heap owned by the elements themselves
+    // (e.g. String contents) and allocator overhead are not included.
+    pub fn memory_footprint(&self) -> usize {
+        let entry_size = std::mem::size_of::<(Segment<T>, TrieNode<T>)>() + 1;
+        let own = self.children.capacity() * entry_size;
+        own + self.children.iter()
+            .map(|(segment, child)| segment.len() * std::mem::size_of::<T>() + child.memory_footprint())
+            .sum::<usize>()
+    }
+
+    // Mean Shannon entropy, in bits, over all internal nodes of how the
+    // stored sequences below each node split across its children.
+    // 0.0 for chains (and empty tries); 1.0 for perfectly balanced binary branching.
+    pub fn branching_entropy(&self) -> f64 {
+        let mut entropies = Vec::new();
+        self.collect_branching_entropies(&mut entropies);
+        if entropies.is_empty() {
+            return 0.0;
+        }
+        entropies.iter().sum::<f64>() / entropies.len() as f64
+    }
+
+    fn collect_branching_entropies(&self, entropies: &mut Vec<f64>) {
+        if self.children.is_empty() {
+            return;
+        }
+        let counts: Vec<usize> = self.children.values().map(|child| child.cached.terminal_count).collect();
+        let total: usize = counts.iter().sum();
+        let entropy = counts.iter()
+            .filter(|&&count| count > 0)
+            .map(|&count| {
+                let p = count as f64 / total as f64;
+                -p * p.log2()
+            })
+            .sum();
+        entropies.push(entropy);
+        for child in self.children.values() {
+            child.collect_branching_entropies(entropies);
+        }
+    }
+
+    // (nodes in this trie, nodes an element-per-node trie would need for the
+    // same sequences). Every element of every segment is a distinct prefix,
+    // so the latter is one node per segment element plus the root.
+    pub fn segmentation_savings(&self) -> (usize, usize) {
+        (self.stats().node_count, self.segment_element_count() + 1)
+    }
+
+    // Ratio of stored sequence elements to the elements actually kept in segments.
+    // Values above 1.0 mean shared segments are saving space; an empty trie yields 1.0.
+    // Each distinct sequence counts once: repeated insertions raise frequency, not the ratio.
+    pub fn compression_ratio(&self) -> f64 {
+        let segment_elements = self.segment_element_count();
+        if segment_elements == 0 {
+            return 1.0;
+        }
+        self.stored_element_count(0) as f64 / segment_elements as f64
+    }
+
+    fn segment_element_count(&self) -> usize {
+        self.children.iter()
+            .map(|(segment, child)| segment.len() + child.segment_element_count())
+            .sum()
+    }
+
+    // Sum of the lengths of all stored sequences, `depth` being the elements above this node
+    fn stored_element_count(&self, depth: usize) -> usize {
+        let own = if self.is_terminal { depth } else { 0 };
+        own + self.children.iter()
+            .map(|(segment, child)| child.stored_element_count(depth + segment.len()))
+            .sum::<usize>()
+    }
+
+    // Export the raw graph: nodes numbered in pre-order (root is 0) and
+    // (parent id, child id, segment) edges, for use with any graph library
+    pub fn to_edges(&self) -> (Vec<NodeInfo>, Vec<Edge<T>>) {
+        let order = self.preorder();
+        let nodes = order.iter().enumerate()
+            .map(|(id, entry)| NodeInfo {
+                id,
+                is_terminal: entry.node.is_terminal,
+                frequency: entry.node.frequency,
+            })
+            .collect();
+        let edges = order.iter().enumerate()
+            .filter_map(|(id, entry)| entry.edge.map(|(parent, segment)| (parent, id, segment.to_vec())))
+            .collect();
+        (nodes, edges)
+    }
+
+    // Single pre-order walk in map iteration order; entry `i` is the node
+    // with id `i`. Shared by `to_edges` and the id column of the printers.
+    fn preorder(&self) -> Vec<PreorderEntry<'_, T>> {
+        let mut order = vec![PreorderEntry { node: self, edge: None }];
+        self.preorder_children(0, &mut order);
+        order
+    }
+
+    fn preorder_children<'a>(&'a self, id: usize, order: &mut Vec<PreorderEntry<'a, T>>) {
+        for (segment, child) in &self.children {
+            let child_id = order.len();
+            order.push(PreorderEntry { node: child, edge: Some((id, segment)) });
+            child.preorder_children(child_id, order);
+        }
+    }
+
+    // Graphviz DOT digraph using the `to_edges` node ids (`n0` is the root).
+    // Terminal nodes are drawn as double circles; edges are labelled with
+    // their segment's elements, space separated.
+    pub fn to_dot(&self) -> String {
+        self.write_dot(&|item: &T| item.to_string(), false)
+    }
+
+    // Like `to_dot`, but also pins every depth level to its own rank so
+    // Graphviz lays the trie out in horizontal tiers
+    pub fn to_dot_ranked(&self) -> String {
+        self.write_dot(&|item: &T| item.to_string(), true)
+    }
+
+    // Like `to_dot`, with each element of an edge label rendered by
+    // `label_fn`. DOT escaping is applied to the joined result afterwards.
+    pub fn to_dot_with<F: Fn(&T) -> String>(&self, label_fn: F) -> String {
+        self.write_dot(&label_fn, false)
+    }
+
+    fn write_dot(&self, label: &dyn Fn(&T) -> String, ranked: bool) -> String {
+        let mut out = String::from("digraph trie {\n");
+        let mut levels: Vec<Vec<usize>> = Vec::new();
+        self.write_dot_node(&mut out, label, 0, &mut 0, &mut levels);
+        if ranked {
+            for level in &levels {
+                let ids: Vec<String> = level.iter().map(|id| format!("n{};", id)).collect();
+                out.push_str(&format!("  {{rank=same; {}}}\n", ids.join(" ")));
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn write_dot_node(
+        &self,
+        out: &mut String,
+        label: &dyn Fn(&T) -> String,
+        depth: usize,
+        next_id: &mut usize,
+        levels: &mut Vec<Vec<usize>>,
+    ) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        if levels.len() <= depth {
+            levels.push(Vec::new());
+        }
+        levels[depth].push(id);
+
+        let shape = if self.is_terminal { "doublecircle" } else { "circle" };
+        out.push_str(&format!("  n{} [label=\"\", shape={}];\n", id, shape));
+        for (segment, child) in &self.children {
+            let child_id = child.write_dot_node(out, label, depth + 1, next_id, levels);
+            let text = segment.iter().map(label).collect::<Vec<_>>().join(" ");
+            out.push_str(&format!("  n{} -> n{} [label={}];\n", id, child_id, dot_string(&text)));
+        }
+        id
+    }
+
+    // Newick tree, e.g. `((car[r],cat[t])[ca],dog[dog]);` for cat/car/dog.
+    // Internal nodes are unnamed and leaves are named by their full path,
+    // elements joined with `separator`. Each branch carries its segment as
+    // a bracketed comment. A terminal internal node gets an extra leaf with
+    // its own path, since only leaves are taxa. Siblings are ordered by
+    // segment text; labels with reserved characters are single-quoted.
+    pub fn to_newick(&self, separator: &str) -> String {
+        let mut out = String::new();
+        self.write_newick(&mut Vec::new(), separator, &mut out);
+        out.push(';');
+        out
+    }
+
+    fn write_newick(&self, path: &mut Vec<String>, separator: &str, out: &mut String) {
+        if self.children.is_empty() {
+            if self.is_terminal || !path.is_empty() {
+                out.push_str(&newick_label(&path.join(separator)));
+            }
+            return;
+        }
+
+        let mut children: Vec<(String, &Segment<T>, &TrieNode<T>)> = self.children.iter()
+            .map(|(segment, child)| {
+                let text = segment.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(separator);
+                (text, segment, child)
+            })
+            .collect();
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+
+        out.push('(');
+        if self.is_terminal {
+            out.push_str(&newick_label(&path.join(separator)));
+            out.push(',');
+        }
+        for (i, (text, segment, child)) in children.into_iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let depth = path.len();
+            path.extend(segment.iter().map(|item| item.to_string()));
+            child.write_newick(path, separator, out);
+            path.truncate(depth);
+            out.push_str(&format!("[{}]", newick_label(&text)));
+        }
+        out.push(')');
+    }
+
+    // Reparseable text form: one segment per line, indented two spaces per
+    // level, with `*` marking terminals (`*N` when inserted N > 1 times).
+    // A leading bare `*` line marks a terminal root. Siblings are ordered by
+    // their rendered text; `\\`, `*`, newlines and a leading space are escaped.
+    pub fn to_trie_string(&self) -> String {
+        let mut out = String::new();
+        if self.is_terminal {
+            out.push_str(&Self::terminal_marker(self.frequency));
+            out.push('\n');
+        }
+        self.write_trie_string(0, &mut out);
+        out
+    }
+
+    fn write_trie_string(&self, depth: usize, out: &mut String) {
+        let mut children: Vec<(String, &TrieNode<T>)> = self.children.iter()
+            .map(|(segment, child)| (Self::escape_segment(segment), child))
+            .collect();
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (text, child) in children {
+            out.push_str(&"  ".repeat(depth));
+            out.push_str(&text);
+            if child.is_terminal {
+                out.push_str(&Self::terminal_marker(child.frequency));
+            }
+            out.push('\n');
+            child.write_trie_string(depth + 1, out);
+        }
+    }
+
+    fn terminal_marker(frequency: usize) -> String {
+        if frequency > 1 {
+            format!("*{}", frequency)
+        } else {
+            "*".to_string()
+        }
+    }
+
+    fn escape_segment(segment: &[T]) -> String {
+        let text: String = segment.iter().map(|item| item.to_string()).collect();
+        let mut escaped = String::with_capacity(text.len());
+        for (i, c) in text.chars().enumerate() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '*' => escaped.push_str("\\*"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                ' ' if i == 0 => escaped.push_str("\\ "),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    // Stream one JSON object per node, one per line, for bulk loaders:
+    // {"id", "parent" (null for the root), "segment", "terminal", "frequency"}.
+    // Ids are assigned in pre-order and match `to_edges`.
+    pub fn write_jsonl<W: std::io::Write>(&self, out: &mut W) -> std::io::Result<()> {
+        let mut next_id = 0;
+        self.write_jsonl_node(out, None, &[], &mut next_id)
+    }
+
+    fn write_jsonl_node<W: std::io::Write>(
+        &self,
+        out: &mut W,
+        parent: Option<usize>,
+        segment: &[T],
+        next_id: &mut usize,
+    ) -> std::io::Result<()> {
+        let id = *next_id;
+        *next_id += 1;
+
+        let parent = parent.map_or_else(|| "null".to_string(), |parent| parent.to_string());
+        let elements: Vec<String> = segment.iter()
+            .map(|item| json_string(&item.to_string()))
+            .collect();
+        writeln!(
+            out,
+            "{{\"id\": {}, \"parent\": {}, \"segment\": [{}], \"terminal\": {}, \"frequency\": {}}}",
+            id, parent, elements.join(", "), self.is_terminal, self.frequency
+        )?;
+
+        for (child_segment, child) in &self.children {
+            child.write_jsonl_node(out, Some(id), child_segment, next_id)?;
+        }
+        Ok(())
+    }
+
+    // YAML view: each node is a mapping with `terminal` and a `children`
+    // sequence; children also carry their `segment` as a flow sequence of
+    // elements, quoted where YAML would misread them. Siblings are ordered
+    // by their rendered segment.
+    pub fn to_yaml(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format!("terminal: {}\n", self.is_terminal));
+        self.write_yaml_children("", &mut out);
+        out
+    }
+
+    fn write_yaml_children(&self, indent: &str, out: &mut String) {
+        if self.children.is_empty() {
+            out.push_str(&format!("{}children: []\n", indent));
+            return;
+        }
+        out.push_str(&format!("{}children:\n", indent));
+
+        let mut children: Vec<(String, &TrieNode<T>)> = self.children.iter()
+            .map(|(segment, child)| {
+                let elements: Vec<String> = segment.iter()
+                    .map(|item| yaml_scalar(&item.to_string()))
+                    .collect();
+                (format!("[{}]", elements.join(", ")), child)
+            })
+            .collect();
+        children.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let child_indent = format!("{}    ", indent);
+        for (segment, child) in children {
+            out.push_str(&format!("{}  - segment: {}\n", indent, segment));
+            out.push_str(&format!("{}terminal: {}\n", child_indent, child.is_terminal));
+            child.write_yaml_children(&child_indent, out);
+        }
+    }
+
+    // Tree visualization method for regular trie
+    pub fn print_tree(&self) {
+        self.print_tree_with_options(" ", ".", false);
+    }
+
+    pub fn print_tree_with_options(&self, separator: &str, terminal_char: &str, quote_elements: bool) {
+        print!("{}", self.format_tree(separator, terminal_char, quote_elements));
+    }
+
+    // Render the tree into a String; see `write_tree` for streaming output
+    pub fn format_tree(&self, separator: &str, terminal_char: &str, quote_elements: bool) -> String {
+        let mut out = String::new();
+        self.write_tree(&mut out, separator, terminal_char, quote_elements)
+            .expect("writing to a String cannot fail");
+        out
+    }
+
+    // Stream the tree line by line into any fmt::Write sink
+    pub fn write_tree<W: std::fmt::Write>(
+        &self,
+        out: &mut W,
+        separator: &str,
+        terminal_char: &str,
+        quote_elements: bool,
+    ) -> std::fmt::Result {
+        let style = TreeStyle::new(separator, terminal_char, quote_elements);
+        self.write_tree_styled(out, &style)
+    }
+
+    pub fn print_tree_with(&self, options: &PrintOptions) {
+        print!("{}", self.format_tree_with(options));
+    }
+
+    pub fn format_tree_with(&self, options: &PrintOptions) -> String {
+        let style = TreeStyle {
+            terminals_only: options.terminals_only,
+            show_ids: options.show_ids,
+            ..TreeStyle::new(&options.separator, &options.terminal_char, options.quote_elements)
+        };
+        let mut out = String::new();
+        self.write_tree_styled(&mut out, &style)
+            .expect("writing to a String cannot fail");
+        out
+    }
+
+    // Print the tree with segments rendered by a custom formatter
+    pub fn print_tree_with_formatter(&self, formatter: &dyn SegmentFormatter<T>) {
+        print!("{}", self.format_tree_with_formatter(".", formatter));
+    }
+
+    pub fn format_tree_with_formatter(&self, terminal_char: &str, formatter: &dyn SegmentFormatter<T>) -> String {
+        let style = TreeStyle { formatter: Some(formatter), ..TreeStyle::new(" ", terminal_char, false) };
+        let mut out = String::new();
+        self.write_tree_styled(&mut out, &style)
+            .expect("writing to a String cannot fail");
+        out
+    }
+
+    // Print the tree with every edge annotated by the fraction of its parent's
+    // total terminal weight (insertion counts) that flows through it
+    pub fn print_tree_weighted(&self) {
+        print!("{}", self.format_tree_weighted());
+    }
+
+    pub fn format_tree_weighted(&self) -> String {
+        let style = TreeStyle { weighted: true, ..TreeStyle::new(" ", ".", false) };
+        let mut out = String::new();
+        self.write_tree_styled(&mut out, &style)
+            .expect("writing to a String cannot fail");
+        out
+    }
+
+    // Like `format_tree`, but siblings are ordered by comparing their segments
+    // element-wise with `compare` (e.g. a locale-aware collation)
+    pub fn format_tree_sorted_by<F>(
+        &self,
+        separator: &str,
+        terminal_char: &str,
+        quote_elements: bool,
+        compare: F,
+    ) -> String
+    where
+        F: Fn(&T, &T) -> Ordering,
+    {
+        let mut out = String::new();
+        self.write_tree_sorted_by(&mut out, separator, terminal_char, quote_elements, compare)
+            .expect("writing to a String cannot fail");
+        out
+    }
+
+    pub fn write_tree_sorted_by<W, F>(
+        &self,
+        out: &mut W,
+        separator: &str,
+        terminal_char: &str,
+        quote_elements: bool,
+        compare: F,
+    ) -> std::fmt::Result
+    where
+        W: std::fmt::Write,
+        F: Fn(&T, &T) -> Ordering,
+    {
+        let style = TreeStyle {
+            order: Some(&compare),
+            ..TreeStyle::new(separator, terminal_char, quote_elements)
+        };
+        self.write_tree_styled(out, &style)
+    }
+
+    fn write_tree_styled<W: std::fmt::Write>(&self, out: &mut W, style: &TreeStyle<'_, T>) -> std::fmt::Result {
+        if style.terminals_only {
+            let mut lines: Vec<String> = self.iter_sequences()
+                .map(|seq| Self::format_segment(&seq, style.separator, style.quote_elements))
+                .collect();
+            lines.sort();
+            return lines.iter().try_for_each(|line| writeln!(out, "{}", line));
+        }
+        let index = self.print_index(style);
+        if style.show_ids {
+            write!(out, "{:>width$} ", 0, width = index.id_width)?;
+        }
+        writeln!(out, "Root{}", if self.is_terminal { style.terminal_char } else { "" })?;
+        self.write_tree_recursive(out, "", style, (&index, 0))
+    }
+
+    // Ids come from the same pre-order walk as `to_edges`; weights are summed
+    // bottom-up over that walk, since every child's id follows its parent's
+    fn print_index(&self, style: &TreeStyle<'_, T>) -> PrintIndex<'_, T> {
+        let mut index = PrintIndex { child_ids: HashMap::new(), weights: Vec::new(), id_width: 0 };
+        if !style.show_ids && !style.weighted {
+            return index;
+        }
+        let order = self.preorder();
+        index.weights = order.iter().map(|entry| entry.node.frequency).collect();
+        for (id, entry) in order.iter().enumerate().rev() {
+            if let Some((parent, segment)) = entry.edge {
+                index.weights[parent] += index.weights[id];
+                index.child_ids.insert((parent, segment), id);
+            }
+        }
+        // Ids are right-aligned so the tree drawing stays lined up
+        if style.show_ids {
+            index.id_width = (order.len() - 1).to_string().len();
+        }
+        index
+    }
+
+    // `node` is `(print index, this node's id)`
+    fn write_tree_recursive<W: std::fmt::Write>(
+        &self,
+        out: &mut W,
+        prefix: &str,
+        style: &TreeStyle<'_, T>,
+        node: (&PrintIndex<'_, T>, usize),
+    ) -> std::fmt::Result {
+        let (index, own_id) = node;
+        let mut children: Vec<_> = self.children.iter().collect();
+        if let Some(compare) = style.order {
+            children.sort_by(|a, b| Self::compare_segments(a.0, b.0, compare));
+        }
+        
+        let displays = Self::sibling_displays(&children, style);
+        let total_weight = if style.weighted { index.weights[own_id] } else { 0 };
+        
+        for (i, ((segment, child), segment_display)) in children.iter().zip(displays).enumerate() {
+            let is_last = i == children.len() - 1;
+            let branch = if is_last { "└─" } else { "├─" };
+            let child_prefix = if is_last { "  " } else { "│ " };
+            let child_id = index.child_ids.get(&(own_id, &segment[..])).copied().unwrap_or(0);
+
+            if style.show_ids {
+                write!(out, "{:>width$} ", child_id, width = index.id_width)?;
+            }
+            write!(out, "{}{} {}{}", prefix, branch, segment_display, 
+                if child.is_terminal { style.terminal_char } else { "" })?;
+            if style.weighted {
+                let share = if total_weight == 0 {
+                    0.0
+                } else {
+                    index.weights[child_id] as f64 / total_weight as f64
+                };
+                write!(out, " ({:.2})", share)?;
+            }
+            writeln!(out)?;
+            
+            child.write_tree_recursive(out, &format!("{}{}", prefix, child_prefix), style, (index, child_id))?;
+        }
+        Ok(())
+    }
+
+    // Display strings for a sibling set. When `Display` renders two different
+    // segments identically, the colliding siblings fall back to `Debug`.
+    fn sibling_displays(children: &[(&Segment<T>, &TrieNode<T>)], style: &TreeStyle<'_, T>) -> Vec<String> {
+        if let Some(formatter) = style.formatter {
+            return children.iter().map(|(segment, _)| formatter.format(segment)).collect();
+        }
+        let mut displays: Vec<String> = children.iter()
+            .map(|(segment, _)| Self::format_segment(segment, style.separator, style.quote_elements))
+            .collect();
+        if style.quote_elements {
+            return displays;
+        }
+
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        for display in &displays {
+            *seen.entry(display.as_str()).or_insert(0) += 1;
+        }
+        let colliding: Vec<bool> = displays.iter().map(|display| seen[display.as_str()] > 1).collect();
+        for (i, (segment, _)) in children.iter().enumerate() {
+            if colliding[i] {
+                displays[i] = Self::format_segment(segment, style.separator, true);
+            }
+        }
+        displays
+    }
+
+    // Lexicographic segment comparison driven by an element comparator
+    fn compare_segments(a: &[T], b: &[T], compare: &dyn Fn(&T, &T) -> Ordering) -> Ordering {
+        for (x, y) in a.iter().zip(b.iter()) {
+            let ordering = compare(x, y);
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        a.len().cmp(&b.len())
+    }
+
+    fn format_segment(segment: &[T], separator: &str, quote_elements: bool) -> String {
+        segment.iter()
+            .map(|item| {
+                if quote_elements {
+                    format!("{:?}", item)
+                } else {
+                    format!("{}", item)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+}
+
+// Settings for `LengthGroupedNode::format_tree_with`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupedPrintOptions {
+    // Placed between the elements of a segment
+    pub separator: String,
+    // Appended to terminal nodes (unless `end_marker` is set)
+    pub terminal_char: String,
+    // Show terminality as an explicit `<end>` first child line instead of
+    // a marker on the node's own line
+    pub end_marker: bool,
+    // Order of sibling groups
+    pub sort: SortOrder,
+}
+
+impl Default for GroupedPrintOptions {
+    fn default() -> Self {
+        GroupedPrintOptions {
+            separator: " ".to_string(),
+            terminal_char: ".".to_string(),
+            end_marker: false,
+            sort: SortOrder::LengthAsc,
+        }
+    }
+}
+
+// Sibling order for the grouped tree printer. The length orders break ties
+// by segments and the segment orders by length, in the same direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    #[default]
+    LengthAsc,
+    LengthDesc,
+    SegmentAsc,
+    SegmentDesc,
+    // Whatever order the children map yields
+    Unsorted,
+}
+
+// Generic Length-grouped trie implementation. Keys order by length, then
+// by segment set.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LengthGroupKey<T> 
+where 
+    T: Clone + Eq + Ord + Hash + Debug,
+{
+    length: usize,
+    segments: BTreeSet<Vec<T>>,
+}
+
+impl<T> LengthGroupKey<T> 
+where 
+    T: Clone + Eq + Ord + Hash + Debug,
+{
+    // Unchecked: `length` is not compared with the segments. Use `try_new`
+    // to reject keys whose segments are not all `length` long.
+    pub fn new(length: usize, segments: BTreeSet<Vec<T>>) -> Self {
+        LengthGroupKey { length, segments }
+    }
+
+    pub fn try_new(length: usize, segments: BTreeSet<Vec<T>>) -> Result<Self, KeyError> {
+        let key = LengthGroupKey { length, segments };
+        key.check()?;
+        Ok(key)
+    }
+
+    // Fails unless every segment is exactly `length` long
+    fn check(&self) -> Result<(), KeyError> {
+        if self.segments.iter().any(|seg| seg.len() != self.length) {
+            let found: BTreeSet<usize> = self.segments.iter().map(|seg| seg.len()).collect();
+            return Err(KeyError::LengthMismatch { length: self.length, found: found.into_iter().collect() });
+        }
+        Ok(())
+    }
+}
+
+// Error returned by `LengthGroupKey::try_new` and `LengthGroupedNode::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyError {
+    // Some segment is not `length` long; `found` lists the distinct segment lengths
+    LengthMismatch { length: usize, found: Vec<usize> },
+}
+
+impl std::fmt::Display for KeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyError::LengthMismatch { length, found } => write!(
+                f,
+                "group key length {} does not match segment lengths {:?}",
+                length, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+// Summary of one grouped node's length groups, as returned by
+// `LengthGroupedNode::compression_report`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GroupReport {
+    pub group_count: usize,
+    pub average_segments_per_group: f64,
+    // (group length, number of distinct trie segments merged into it),
+    // sorted by length
+    pub collapsed_children: Vec<(usize, usize)>,
+}
+
+// Equality compares terminal flags and length-group keys recursively. Since
+// grouping is lossy, equal grouped tries may come from different base tries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LengthGroupedNode<T> 
+where 
+    T: Clone + Eq + Hash + Debug + std::cmp::Ord,
+{
+    children: HashMap<LengthGroupKey<T>, LengthGroupedNode<T>>,
+    is_terminal: bool,
+}
+
+// Same-length siblings with equal subtrees, grouped by `from_trie_conservative`
+struct SiblingClass<'a, T> {
+    length: usize,
+    representative: &'a TrieNode<T>,
+    segments: BTreeSet<Vec<T>>,
+}
+
+impl<T> LengthGroupedNode<T>
+where 
+    T: Clone + Eq + Ord + Hash + Debug,
+{
+    pub fn new() -> Self {
+        LengthGroupedNode {
+            children: HashMap::new(),
+            is_terminal: false,
+        }
+    }
+
+    // Build a grouped node by hand, e.g. to describe an expected tree in tests
+    pub fn with_children(is_terminal: bool, children: HashMap<LengthGroupKey<T>, LengthGroupedNode<T>>) -> Self {
+        LengthGroupedNode { children, is_terminal }
+    }
+
+    pub fn children(&self) -> &HashMap<LengthGroupKey<T>, LengthGroupedNode<T>> {
+        &self.children
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        self.is_terminal
+    }
+
+    pub fn set_terminal(&mut self, value: bool) {
+        self.is_terminal = value;
+    }
+
+    pub fn num_children(&self) -> usize {
+        self.children.len()
+    }
+
+    pub fn from_trie(trie_root: &TrieNode<T>) -> Self {
+        Self::transform_node_recursive(trie_root, usize::MAX)
+    }
+
+    // Like `from_trie`, but grouping stops after `max_depth` group levels
+    // below the root. Deeper groups are truncated; nodes at the cut keep
+    // their own terminal flag.
+    pub fn from_trie_to_depth(trie_root: &TrieNode<T>, max_depth: usize) -> Self {
+        Self::transform_node_recursive(trie_root, max_depth)
+    }
+
+    // Like `from_trie`, but same-length siblings are only grouped when their
+    // subtrees are equal (`TrieNode` equality); siblings whose continuations
+    // differ get separate groups of the same length
+    pub fn from_trie_conservative(trie_root: &TrieNode<T>) -> Self {
+        let mut new_node = LengthGroupedNode::new();
+        new_node.is_terminal = trie_root.is_terminal;
+
+        // Classes of same-length siblings with equal subtrees
+        let mut classes: Vec<SiblingClass<T>> = Vec::new();
+        for (segment, child) in &trie_root.children {
+            match classes.iter_mut().find(|class| {
+                class.length == segment.len() && class.representative == child
+            }) {
+                Some(class) => {
+                    class.segments.insert(segment.to_vec());
+                }
+                None => classes.push(SiblingClass {
+                    length: segment.len(),
+                    representative: child,
+                    segments: BTreeSet::from([segment.to_vec()]),
+                }),
+            }
+        }
+
+        for class in classes {
+            new_node.children.insert(
+                LengthGroupKey::new(class.length, class.segments),
+                Self::from_trie_conservative(class.representative),
+            );
+        }
+        new_node
+    }
+
+    // `levels_left` counts the group levels still to be built below this node
+    fn transform_node_recursive(current_node: &TrieNode<T>, levels_left: usize) -> Self {
         let mut new_node = LengthGroupedNode::new();
         new_node.is_terminal = current_node.is_terminal;
 
-        // If this is a leaf node, return immediately
-        if current_node.children.is_empty() {
-            return new_node;
+        // If this is a leaf node (or the depth cap is reached), return immediately
+        if current_node.children.is_empty() || levels_left == 0 {
+            return new_node;
+        }
+
+        // Group children by segment length
+        let mut length_groups: HashMap<usize, BTreeSet<Vec<T>>> = HashMap::new();
+        
+        for (segment, _) in &current_node.children {
+            let segment_length = segment.len();
+            length_groups.entry(segment_length)
+                .or_insert_with(BTreeSet::new)
+                .insert(segment.to_vec());
+        }
+
+        // Process each length group recursively
+        for (length, segment_set) in length_groups {
+            let group_key = LengthGroupKey::new(length, segment_set.clone());
+            
+            // Merge all children in this length group
+            let merged_child = Self::merge_children_by_length_group(
+                &current_node.children,
+                &segment_set,
+                levels_left - 1,
+            );
+            
+            new_node.children.insert(group_key, merged_child);
+        }
+
+        new_node
+    }
+
+    fn merge_children_by_length_group(
+        original_children: &HashMap<Segment<T>, TrieNode<T>>,
+        segments_in_group: &BTreeSet<Vec<T>>,
+        levels_left: usize,
+    ) -> Self {
+        let mut merged_node = LengthGroupedNode::new();
+        
+        // Collect all grandchildren from all segments in this group
+        let mut all_grandchildren: HashMap<Segment<T>, TrieNode<T>> = HashMap::new();
+        let mut any_terminal = false;
+
+        for segment in segments_in_group {
+            if let Some(child_node) = original_children.get(segment.as_slice()) {
+                // Check if any child in this group is terminal
+                if child_node.is_terminal {
+                    any_terminal = true;
+                }
+                
+                // Collect all grandchildren
+                for (grandchild_segment, grandchild_node) in &child_node.children {
+                    all_grandchildren.insert(grandchild_segment.clone(), grandchild_node.clone());
+                }
+            }
+        }
+
+        merged_node.is_terminal = any_terminal;
+        if levels_left == 0 {
+            return merged_node;
+        }
+
+        // Now group all grandchildren by their segment lengths
+        let mut length_groups: HashMap<usize, BTreeSet<Vec<T>>> = HashMap::new();
+        
+        for (segment, _) in &all_grandchildren {
+            let segment_length = segment.len();
+            length_groups.entry(segment_length)
+                .or_insert_with(BTreeSet::new)
+                .insert(segment.to_vec());
+        }
+
+        // Process each length group recursively
+        for (length, segment_set) in length_groups {
+            let group_key = LengthGroupKey::new(length, segment_set.clone());
+            
+            // Recursively merge this length group
+            let merged_child = Self::merge_children_by_length_group(
+                &all_grandchildren,
+                &segment_set,
+                levels_left - 1,
+            );
+            
+            merged_node.children.insert(group_key, merged_child);
+        }
+
+        merged_node
+    }
+
+    // Check that every segment of every group key is exactly the key's
+    // declared `length`. Keys mixing segment lengths or declaring a
+    // different length are reported; nothing is re-keyed.
+    pub fn validate(&self) -> Result<(), KeyError> {
+        for (key, child) in &self.children {
+            key.check()?;
+            child.validate()?;
+        }
+        Ok(())
+    }
+
+    // Nested JSON view of the grouped tree. Each non-root node carries the
+    // `length` and `segments` of the key leading to it; elements are rendered
+    // with `Display` as JSON strings and children are sorted by (length, segments).
+    pub fn to_json(&self) -> String
+    where
+        T: Display,
+    {
+        let mut out = String::new();
+        self.write_json(None, &mut out);
+        out
+    }
+
+    fn write_json(&self, key: Option<&LengthGroupKey<T>>, out: &mut String)
+    where
+        T: Display,
+    {
+        out.push_str(&format!("{{\"terminal\": {}", self.is_terminal));
+        if let Some(key) = key {
+            let segments: Vec<String> = key.segments.iter()
+                .map(|seg| {
+                    let elements: Vec<String> = seg.iter()
+                        .map(|item| json_string(&item.to_string()))
+                        .collect();
+                    format!("[{}]", elements.join(", "))
+                })
+                .collect();
+            out.push_str(&format!(", \"length\": {}, \"segments\": [{}]", key.length, segments.join(", ")));
+        }
+        out.push_str(", \"children\": [");
+
+        let mut children: Vec<_> = self.children.iter().collect();
+        children.sort_by(|a, b| {
+            a.0.length.cmp(&b.0.length)
+                .then_with(|| a.0.segments.cmp(&b.0.segments))
+        });
+        for (i, (child_key, child)) in children.into_iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            child.write_json(Some(child_key), out);
+        }
+        out.push_str("]}");
+    }
+
+    // Every distinct group key used anywhere in the structure: the
+    // length/segment-set combinations the grouping produced
+    pub fn all_group_keys(&self) -> BTreeSet<LengthGroupKey<T>> {
+        let mut keys = BTreeSet::new();
+        self.collect_group_keys(&mut keys);
+        keys
+    }
+
+    fn collect_group_keys(&self, keys: &mut BTreeSet<LengthGroupKey<T>>) {
+        for (key, child) in &self.children {
+            keys.insert(key.clone());
+            child.collect_group_keys(keys);
+        }
+    }
+
+    // Nodes in the grouped tree, including this one
+    pub fn node_count(&self) -> usize {
+        1 + self.children.values().map(|child| child.node_count()).sum::<usize>()
+    }
+
+    // Share every structurally identical subtree across the whole structure,
+    // not just among siblings, giving a read-only DAG
+    pub fn canonicalize(&self) -> CanonicalGroupedDag<T> {
+        let mut nodes = Vec::new();
+        let root = CanonicalGroupedDag::intern(&mut nodes, &mut HashMap::new(), self);
+        CanonicalGroupedDag { nodes, root }
+    }
+
+    // The group lengths along every root-to-terminal path, i.e. the shapes
+    // of the sequences this grouped trie represents (sorted, deduplicated)
+    pub fn length_signatures(&self) -> Vec<Vec<usize>> {
+        let mut signatures = BTreeSet::new();
+        self.collect_length_signatures(&mut Vec::new(), &mut signatures);
+        signatures.into_iter().collect()
+    }
+
+    fn collect_length_signatures(&self, path: &mut Vec<usize>, signatures: &mut BTreeSet<Vec<usize>>) {
+        if self.is_terminal {
+            signatures.insert(path.clone());
+        }
+        for (key, child) in &self.children {
+            path.push(key.length);
+            child.collect_length_signatures(path, signatures);
+            path.pop();
+        }
+    }
+
+    // Shortest and longest segment length among the group keys at each
+    // level, one entry per depth (index 0 covers the root's children)
+    pub fn length_group_ranges(&self) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        self.collect_length_ranges(0, &mut ranges);
+        ranges
+    }
+
+    fn collect_length_ranges(&self, level: usize, ranges: &mut Vec<(usize, usize)>) {
+        for (key, child) in &self.children {
+            for segment in &key.segments {
+                if ranges.len() <= level {
+                    ranges.push((segment.len(), segment.len()));
+                }
+                let (min, max) = &mut ranges[level];
+                *min = (*min).min(segment.len());
+                *max = (*max).max(segment.len());
+            }
+            child.collect_length_ranges(level + 1, ranges);
+        }
+    }
+
+    // How much the length grouping abstracted at this node. Each key keeps
+    // the set of trie segments it replaced, so the collapsed counts are the
+    // sizes of those sets.
+    pub fn compression_report(&self) -> GroupReport {
+        let mut collapsed_children: Vec<(usize, usize)> = self.children.keys()
+            .map(|key| (key.length, key.segments.len()))
+            .collect();
+        collapsed_children.sort();
+
+        let segments: usize = collapsed_children.iter().map(|(_, count)| count).sum();
+        let average_segments_per_group = if collapsed_children.is_empty() {
+            0.0
+        } else {
+            segments as f64 / collapsed_children.len() as f64
+        };
+
+        GroupReport {
+            group_count: collapsed_children.len(),
+            average_segments_per_group,
+            collapsed_children,
+        }
+    }
+
+    // Original print method (kept for backward compatibility)
+    pub fn print(&self, depth: usize) {
+        self.print_with(depth, 2, "Terminal");
+    }
+
+    pub fn print_with(&self, depth: usize, indent_width: usize, terminal_label: &str) {
+        print!("{}", self.format_flat(depth, indent_width, terminal_label));
+    }
+
+    // The `print` layout as a String: `indent_width` spaces per level, and
+    // `terminal_label` on its own line under terminal nodes
+    pub fn format_flat(&self, depth: usize, indent_width: usize, terminal_label: &str) -> String {
+        let mut out = String::new();
+        self.write_flat(&mut out, depth, indent_width, terminal_label);
+        out
+    }
+
+    fn write_flat(&self, out: &mut String, depth: usize, indent_width: usize, terminal_label: &str) {
+        let indent = " ".repeat(depth * indent_width);
+        
+        if self.is_terminal {
+            out.push_str(&format!("{}{}\n", indent, terminal_label));
+        }
+
+        for (key, child) in &self.children {
+            let segments: Vec<Vec<T>> = key.segments.iter().cloned().collect();
+            out.push_str(&format!("{}({}, {:?}):\n", indent, key.length, segments));
+            child.write_flat(out, depth + 1, indent_width, terminal_label);
+        }
+    }
+
+    // Tree visualization method for length-grouped trie
+    pub fn print_tree(&self) {
+        self.print_tree_with_options(" ", ".");
+    }
+
+    pub fn print_tree_with_options(&self, separator: &str, terminal_char: &str) {
+        print!("{}", self.format_tree_with(&GroupedPrintOptions {
+            separator: separator.to_string(),
+            terminal_char: terminal_char.to_string(),
+            ..GroupedPrintOptions::default()
+        }));
+    }
+
+    pub fn format_tree_with(&self, options: &GroupedPrintOptions) -> String {
+        let mut out = String::new();
+        let marker = if self.is_terminal && !options.end_marker { options.terminal_char.as_str() } else { "" };
+        out.push_str(&format!("Root{}\n", marker));
+        self.write_tree_recursive(&mut out, "", options);
+        out
+    }
+
+    fn write_tree_recursive(&self, out: &mut String, prefix: &str, options: &GroupedPrintOptions) {
+        let mut children: Vec<_> = self.children.iter().collect();
+        let by_length = |a: &LengthGroupKey<T>, b: &LengthGroupKey<T>| {
+            a.length.cmp(&b.length).then_with(|| a.segments.cmp(&b.segments))
+        };
+        let by_segments = |a: &LengthGroupKey<T>, b: &LengthGroupKey<T>| {
+            a.segments.cmp(&b.segments).then_with(|| a.length.cmp(&b.length))
+        };
+        match options.sort {
+            SortOrder::LengthAsc => children.sort_by(|a, b| by_length(a.0, b.0)),
+            SortOrder::LengthDesc => children.sort_by(|a, b| by_length(b.0, a.0)),
+            SortOrder::SegmentAsc => children.sort_by(|a, b| by_segments(a.0, b.0)),
+            SortOrder::SegmentDesc => children.sort_by(|a, b| by_segments(b.0, a.0)),
+            SortOrder::Unsorted => {}
+        }
+
+        // Terminality as its own first child line
+        let end_line = options.end_marker && self.is_terminal;
+        if end_line {
+            let branch = if children.is_empty() { "└─" } else { "├─" };
+            out.push_str(&format!("{}{} <end>\n", prefix, branch));
+        }
+        
+        for (i, (key, child)) in children.iter().enumerate() {
+            let is_last = i == children.len() - 1;
+            let branch = if is_last { "└─" } else { "├─" };
+            let child_prefix = if is_last { "  " } else { "│ " };
+            let separator = options.separator.as_str();
+            
+            // Format the segments more compactly
+            let segments_display = if key.segments.len() == 1 {
+                Self::format_segment(key.segments.iter().next().unwrap(), separator)
+            } else {
+                format!("[{}]", 
+                    key.segments.iter()
+                        .map(|seg| Self::format_segment(seg, separator))
+                        .collect::<Vec<_>>()
+                        .join(", "))
+            };
+            
+            let marker = if child.is_terminal && !options.end_marker { options.terminal_char.as_str() } else { "" };
+            out.push_str(&format!("{}{}len={} {}{}\n", prefix, branch, key.length, segments_display, marker));
+            
+            child.write_tree_recursive(out, &format!("{}{}", prefix, child_prefix), options);
+        }
+    }
+
+    fn format_segment(segment: &[T], separator: &str) -> String {
+        segment.iter()
+            .map(|item| format!("{:?}", item))
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CanonicalNode<T>
+where
+    T: Clone + Eq + Ord + Hash + Debug,
+{
+    is_terminal: bool,
+    // Sorted by key so equal nodes compare and hash equal
+    edges: Vec<(LengthGroupKey<T>, usize)>,
+}
+
+// Read-only DAG produced by `LengthGroupedNode::canonicalize`. Every set of
+// structurally identical grouped subtrees is stored once, wherever in the
+// structure they occur.
+#[derive(Debug, Clone)]
+pub struct CanonicalGroupedDag<T>
+where
+    T: Clone + Eq + Ord + Hash + Debug,
+{
+    nodes: Vec<CanonicalNode<T>>,
+    root: usize,
+}
+
+impl<T> CanonicalGroupedDag<T>
+where
+    T: Clone + Eq + Ord + Hash + Debug,
+{
+    fn intern(
+        nodes: &mut Vec<CanonicalNode<T>>,
+        interned: &mut HashMap<CanonicalNode<T>, usize>,
+        grouped: &LengthGroupedNode<T>,
+    ) -> usize {
+        let mut edges: Vec<(LengthGroupKey<T>, usize)> = grouped.children.iter()
+            .map(|(key, child)| (key.clone(), Self::intern(nodes, interned, child)))
+            .collect();
+        edges.sort();
+        let node = CanonicalNode { is_terminal: grouped.is_terminal, edges };
+        if let Some(&id) = interned.get(&node) {
+            return id;
+        }
+        let id = nodes.len();
+        nodes.push(node.clone());
+        interned.insert(node, id);
+        id
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.nodes.iter().map(|node| node.edges.len()).sum()
+    }
+
+    // Whether `sequence` splits into segments, one from each group key along
+    // some path, ending at a terminal node
+    pub fn contains(&self, sequence: &[T]) -> bool {
+        self.contains_from(self.root, sequence)
+    }
+
+    fn contains_from(&self, id: usize, sequence: &[T]) -> bool {
+        let node = &self.nodes[id];
+        if sequence.is_empty() {
+            return node.is_terminal;
+        }
+        node.edges.iter().any(|(key, target)| {
+            key.segments.iter()
+                .filter(|segment| !segment.is_empty() && sequence.starts_with(segment))
+                .any(|segment| self.contains_from(*target, &sequence[segment.len()..]))
+        })
+    }
+}
+
+// Length-grouped view computed on demand from a borrowed trie.
+// Each node stands for the merged set of source trie nodes it groups; its
+// children are only grouped when `children` is first called, then cached.
+pub struct LazyGroupedNode<'a, T>
+where
+    T: Clone + Eq + Ord + Hash + Debug,
+{
+    sources: Vec<&'a TrieNode<T>>,
+    children: OnceCell<Vec<(LengthGroupKey<T>, LazyGroupedNode<'a, T>)>>,
+    // Number of nodes in the whole lazy tree whose children have been computed
+    expansions: Rc<Cell<usize>>,
+}
+
+impl<'a, T> LazyGroupedNode<'a, T>
+where
+    T: Clone + Eq + Ord + Hash + Debug,
+{
+    pub fn new(trie_root: &'a TrieNode<T>) -> Self {
+        Self::from_sources(vec![trie_root], Rc::new(Cell::new(0)))
+    }
+
+    fn from_sources(sources: Vec<&'a TrieNode<T>>, expansions: Rc<Cell<usize>>) -> Self {
+        LazyGroupedNode {
+            sources,
+            children: OnceCell::new(),
+            expansions,
+        }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        self.sources.iter().any(|node| node.is_terminal)
+    }
+
+    // Length groups below this node, sorted by (length, segments)
+    pub fn children(&self) -> &[(LengthGroupKey<T>, LazyGroupedNode<'a, T>)] {
+        self.children.get_or_init(|| {
+            self.expansions.set(self.expansions.get() + 1);
+
+            // Later sources win on duplicate segments, as in `LengthGroupedNode::from_trie`
+            let mut all_children: HashMap<&'a [T], &'a TrieNode<T>> = HashMap::new();
+            for source in &self.sources {
+                for (segment, child) in &source.children {
+                    all_children.insert(segment, child);
+                }
+            }
+
+            let mut length_groups: BTreeMap<usize, BTreeSet<Vec<T>>> = BTreeMap::new();
+            for segment in all_children.keys() {
+                length_groups.entry(segment.len())
+                    .or_default()
+                    .insert(segment.to_vec());
+            }
+
+            length_groups.into_iter()
+                .map(|(length, segment_set)| {
+                    let sources = segment_set.iter().map(|seg| all_children[seg.as_slice()]).collect();
+                    let child = Self::from_sources(sources, Rc::clone(&self.expansions));
+                    (LengthGroupKey::new(length, segment_set), child)
+                })
+                .collect()
+        })
+    }
+
+    // Number of nodes, across the whole lazy tree, whose children were computed so far
+    pub fn expansions(&self) -> usize {
+        self.expansions.get()
+    }
+
+    // Force the remaining groups and produce the eager equivalent
+    pub fn to_grouped(&self) -> LengthGroupedNode<T> {
+        let mut node = LengthGroupedNode::new();
+        node.is_terminal = self.is_terminal();
+        for (key, child) in self.children() {
+            node.children.insert(key.clone(), child.to_grouped());
+        }
+        node
+    }
+}
+
+// Quote and escape a string as a JSON string literal
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Quote a string as a DOT double-quoted ID
+fn dot_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Newick label, single-quoted (with inner quotes doubled) when it is empty
+// or contains whitespace or one of Newick's reserved characters
+fn newick_label(value: &str) -> String {
+    const RESERVED: &[char] = &[',', '(', ')', ';', ':', '[', ']', '\''];
+    if !value.is_empty() && !value.chars().any(|c| c.is_whitespace() || RESERVED.contains(&c)) {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+impl<T> TrieNode<T>
+where
+    T: Clone + Eq + Ord + Hash + Debug + Display,
+{
+    // Same trie as `from_sequences`, for input sorted lexicographically.
+    // Every group formed during the build stays sorted, so the common prefix
+    // of a group is found from its first and last member instead of scanning
+    // all of them. Sortedness is only checked in debug builds.
+    pub fn from_sorted_sequences(sequences: &[&[T]]) -> Self {
+        debug_assert!(
+            sequences.windows(2).all(|pair| pair[0] <= pair[1]),
+            "from_sorted_sequences requires sorted input"
+        );
+        let options = BuildOptions::default();
+        let mut progress = |_, _| {};
+        let mut ctx = BuildContext::new(&options, sequences.len(), &mut progress);
+        ctx.sorted = true;
+        Self::run_build(sequences, &ctx)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // Branching prefixes (nodes with several children, or terminal nodes
+    // with any) nested under their nearest branching ancestor, together with
+    // the same tree keyed by prefix length, where equal lengths are merged
+    pub fn get_prefixes_dict(&self) -> (PrefixTree<Vec<T>>, PrefixTree<usize>) {
+        let mut prefixes = PrefixTree::default();
+        self.collect_branching_prefixes(&mut Vec::new(), &mut prefixes);
+        let lengths = Self::prefix_lengths(&prefixes);
+        (prefixes, lengths)
+    }
+
+    fn collect_branching_prefixes(&self, path: &mut Vec<T>, tree: &mut PrefixTree<Vec<T>>) {
+        for (segment, child) in &self.children {
+            path.extend_from_slice(segment);
+            let branching = child.children.len() > 1 || (child.is_terminal && !child.children.is_empty());
+            if branching {
+                let subtree = tree.children.entry(path.clone()).or_default();
+                child.collect_branching_prefixes(path, subtree);
+            } else {
+                child.collect_branching_prefixes(path, tree);
+            }
+            path.truncate(path.len() - segment.len());
+        }
+    }
+
+    fn prefix_lengths(prefixes: &PrefixTree<Vec<T>>) -> PrefixTree<usize> {
+        let mut lengths = PrefixTree::default();
+        for (prefix, subtree) in &prefixes.children {
+            lengths.children.entry(prefix.len())
+                .or_insert_with(PrefixTree::default)
+                .merge(Self::prefix_lengths(subtree));
+        }
+        lengths
+    }
+
+    // Flattened path of every node boundary that lies on a stored sequence,
+    // from the empty prefix up to the full sequences; empty for an empty trie
+    pub fn all_prefixes(&self) -> BTreeSet<Vec<T>> {
+        let mut prefixes = BTreeSet::new();
+        self.collect_prefixes(&mut Vec::new(), &mut prefixes);
+        prefixes
+    }
+
+    // Returns whether any stored sequence passes through this node
+    fn collect_prefixes(&self, path: &mut Vec<T>, prefixes: &mut BTreeSet<Vec<T>>) -> bool {
+        let mut used = self.is_terminal;
+        for (segment, child) in &self.children {
+            path.extend_from_slice(segment);
+            used |= child.collect_prefixes(path, prefixes);
+            path.truncate(path.len() - segment.len());
+        }
+        if used {
+            prefixes.insert(path.clone());
+        }
+        used
+    }
+
+    // Every stored sequence with its insertion count, sorted by sequence
+    pub fn to_vec_with_frequencies(&self) -> Vec<(Vec<T>, usize)> {
+        let mut pairs: Vec<(Vec<T>, usize)> = self.iter_sequences()
+            .map(|seq| {
+                let frequency = self.frequency(&seq);
+                (seq, frequency)
+            })
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    // Share common trailing runs: identical subtrees are merged, and sibling
+    // segments leading to the same subtree have their longest common suffix
+    // split off into one shared edge, so `["running", "jumping"]` stores "ing" once
+    pub fn factor_common_suffixes(&self) -> SuffixFactoredTrie<T> {
+        let mut dag = SuffixFactoredTrie { nodes: Vec::new(), root: 0, interned: HashMap::new() };
+        dag.root = dag.factor(self);
+        dag
+    }
+}
+
+// Raw `(is_terminal, edges)` node accepted by `SuffixFactoredTrie::from_nodes`;
+// each edge is `(segment, target index)`
+pub type RawDagNode<T> = (bool, Vec<(Vec<T>, usize)>);
+
+// Error reported when a `SuffixFactoredTrie` is not a well-formed DAG
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DagError {
+    // The root index points past the end of the node list of length `len`
+    MissingRoot { root: usize, len: usize },
+    // An edge points past the end of the node list
+    DanglingEdge { node: usize, target: usize },
+    // `node` is reachable from itself
+    Cycle { node: usize },
+}
+
+impl std::fmt::Display for DagError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DagError::MissingRoot { root, len } => {
+                write!(f, "root {} is out of range for {} nodes", root, len)
+            }
+            DagError::DanglingEdge { node, target } => {
+                write!(f, "node {} has an edge to missing node {}", node, target)
+            }
+            DagError::Cycle { node } => write!(f, "node {} is part of a cycle", node),
+        }
+    }
+}
+
+impl std::error::Error for DagError {}
+
+// DAG produced by `TrieNode::factor_common_suffixes`. Structurally identical
+// nodes are stored once, so several edges may point at the same node.
+// Graphs assembled by hand with `from_nodes` may contain cycles; the
+// traversals below detect them and return `DagError::Cycle` instead of looping.
+#[derive(Debug, Clone)]
+pub struct SuffixFactoredTrie<T> {
+    nodes: Vec<DagNode<T>>,
+    root: usize,
+    interned: HashMap<DagNode<T>, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DagNode<T> {
+    is_terminal: bool,
+    // Sorted by segment so equal nodes compare and hash equal
+    edges: Vec<(Vec<T>, usize)>,
+}
+
+impl<T> SuffixFactoredTrie<T>
+where
+    T: Clone + Eq + Ord + Hash + Debug + Display,
+{
+    fn factor(&mut self, node: &TrieNode<T>) -> usize {
+        let mut edges: Vec<(Vec<T>, usize)> = node.children.iter()
+            .map(|(segment, child)| (segment.to_vec(), self.factor(child)))
+            .collect();
+
+        // Group siblings by target and final element, then split off each
+        // group's shared suffix
+        let mut by_target: BTreeMap<(usize, Option<T>), Vec<Vec<T>>> = BTreeMap::new();
+        for (segment, target) in edges.drain(..) {
+            by_target.entry((target, segment.last().cloned())).or_default().push(segment);
+        }
+        for ((target, _), segments) in by_target {
+            let shared = Self::common_suffix_len(&segments);
+            if segments.len() < 2 || shared == 0 {
+                edges.extend(segments.into_iter().map(|segment| (segment, target)));
+                continue;
+            }
+            let suffix = segments[0][segments[0].len() - shared..].to_vec();
+            let middle = self.intern(DagNode { is_terminal: false, edges: vec![(suffix, target)] });
+            for mut segment in segments {
+                segment.truncate(segment.len() - shared);
+                edges.push((segment, middle));
+            }
+        }
+
+        edges.sort();
+        self.intern(DagNode { is_terminal: node.is_terminal, edges })
+    }
+
+    // Longest shared suffix that still leaves every segment a non-empty head
+    fn common_suffix_len(segments: &[Vec<T>]) -> usize {
+        let shortest = segments.iter().map(|seg| seg.len()).min().unwrap_or(0);
+        let first = &segments[0];
+        (0..shortest.saturating_sub(1))
+            .take_while(|&i| {
+                let element = &first[first.len() - 1 - i];
+                segments.iter().all(|seg| &seg[seg.len() - 1 - i] == element)
+            })
+            .count()
+    }
+
+    fn intern(&mut self, node: DagNode<T>) -> usize {
+        if let Some(&id) = self.interned.get(&node) {
+            return id;
+        }
+        let id = self.nodes.len();
+        self.nodes.push(node.clone());
+        self.interned.insert(node, id);
+        id
+    }
+
+    // Assemble a graph from raw nodes. The root and edge targets are checked;
+    // acyclicity is not.
+    pub fn from_nodes(root: usize, nodes: Vec<RawDagNode<T>>) -> Result<Self, DagError> {
+        let len = nodes.len();
+        if root >= len {
+            return Err(DagError::MissingRoot { root, len });
+        }
+        let mut dag = SuffixFactoredTrie { nodes: Vec::with_capacity(len), root, interned: HashMap::new() };
+        for (id, (is_terminal, mut edges)) in nodes.into_iter().enumerate() {
+            if let Some(&(_, target)) = edges.iter().find(|(_, target)| *target >= len) {
+                return Err(DagError::DanglingEdge { node: id, target });
+            }
+            edges.sort();
+            dag.nodes.push(DagNode { is_terminal, edges });
         }
+        Ok(dag)
+    }
 
-        // Group children by segment length
-        let mut length_groups: HashMap<usize, BTreeSet<Vec<T>>> = HashMap::new();
-        
-        for (segment, _) in &current_node.children {
-            let segment_length = segment.len();
-            length_groups.entry(segment_length)
-                .or_insert_with(BTreeSet::new)
-                .insert(segment.clone());
+    // Fails with the first node found on a cycle reachable from the root.
+    // Shared nodes reached along different paths are fine; only a node
+    // revisited while still on the current path is a cycle.
+    pub fn check_acyclic(&self) -> Result<(), DagError> {
+        let mut on_path = vec![false; self.nodes.len()];
+        let mut done = vec![false; self.nodes.len()];
+        self.check_acyclic_from(self.root, &mut on_path, &mut done)
+    }
+
+    fn check_acyclic_from(&self, id: usize, on_path: &mut [bool], done: &mut [bool]) -> Result<(), DagError> {
+        if on_path[id] {
+            return Err(DagError::Cycle { node: id });
+        }
+        if done[id] {
+            return Ok(());
         }
+        on_path[id] = true;
+        for (_, target) in &self.nodes[id].edges {
+            self.check_acyclic_from(*target, on_path, done)?;
+        }
+        on_path[id] = false;
+        done[id] = true;
+        Ok(())
+    }
 
-        // Process each length group recursively
-        for (length, segment_set) in length_groups {
-            let group_key = LengthGroupKey::new(length, segment_set.clone());
-            
-            // Merge all children in this length group
-            let merged_child = Self::merge_children_by_length_group(
-                &current_node.children,
-                &segment_set,
-            );
-            
-            new_node.children.insert(group_key, merged_child);
+    // Every sequence spelled by a root-to-terminal path, in edge order
+    pub fn iter_sequences(&self) -> Result<SequenceIter<T>, DagError> {
+        self.check_acyclic()?;
+        let mut sequences = Vec::new();
+        self.collect_sequences(self.root, &mut Vec::new(), &mut sequences);
+        Ok(SequenceIter { inner: sequences.into_iter() })
+    }
+
+    fn collect_sequences(&self, id: usize, path: &mut Vec<T>, out: &mut Vec<Vec<T>>) {
+        let node = &self.nodes[id];
+        if node.is_terminal {
+            out.push(path.clone());
+        }
+        for (segment, target) in &node.edges {
+            path.extend_from_slice(segment);
+            self.collect_sequences(*target, path, out);
+            path.truncate(path.len() - segment.len());
         }
+    }
 
-        new_node
+    // Statistics over the nodes reachable from the root, each counted once;
+    // `max_depth` is the longest root-to-node path in edges
+    pub fn stats(&self) -> Result<TrieStats, DagError> {
+        self.check_acyclic()?;
+        let mut heights: Vec<Option<usize>> = vec![None; self.nodes.len()];
+        let max_depth = self.height(self.root, &mut heights);
+        let reachable: Vec<usize> = (0..self.nodes.len()).filter(|&id| heights[id].is_some()).collect();
+        Ok(TrieStats {
+            node_count: reachable.len(),
+            terminal_count: reachable.iter().filter(|&&id| self.nodes[id].is_terminal).count(),
+            max_depth,
+        })
     }
 
-    fn merge_children_by_length_group(
-        original_children: &HashMap<Vec<T>, TrieNode<T>>,
-        segments_in_group: &BTreeSet<Vec<T>>,
-    ) -> Self {
-        let mut merged_node = LengthGroupedNode::new();
-        
-        // Collect all grandchildren from all segments in this group
-        let mut all_grandchildren: HashMap<Vec<T>, TrieNode<T>> = HashMap::new();
-        let mut any_terminal = false;
+    // Longest path below `id`, memoized per node
+    fn height(&self, id: usize, heights: &mut Vec<Option<usize>>) -> usize {
+        if let Some(height) = heights[id] {
+            return height;
+        }
+        let height = self.nodes[id].edges.iter()
+            .map(|(_, target)| self.height(*target, heights) + 1)
+            .max()
+            .unwrap_or(0);
+        heights[id] = Some(height);
+        height
+    }
 
-        for segment in segments_in_group {
-            if let Some(child_node) = original_children.get(segment) {
-                // Check if any child in this group is terminal
-                if child_node.is_terminal {
-                    any_terminal = true;
-                }
-                
-                // Collect all grandchildren
-                for (grandchild_segment, grandchild_node) in &child_node.children {
-                    all_grandchildren.insert(grandchild_segment.clone(), grandchild_node.clone());
-                }
-            }
+    // Box-drawing rendering in the style of `TrieNode::format_tree`, with
+    // each node tagged by its id so shared nodes can be recognised
+    pub fn format_tree(&self) -> Result<String, DagError> {
+        self.check_acyclic()?;
+        let mut out = String::new();
+        let root = &self.nodes[self.root];
+        out.push_str(&format!("Root #{}{}\n", self.root, if root.is_terminal { "." } else { "" }));
+        self.format_tree_recursive(self.root, "", &mut out);
+        Ok(out)
+    }
+
+    fn format_tree_recursive(&self, id: usize, prefix: &str, out: &mut String) {
+        let edges = &self.nodes[id].edges;
+        for (i, (segment, target)) in edges.iter().enumerate() {
+            let is_last = i == edges.len() - 1;
+            let branch = if is_last { "└─" } else { "├─" };
+            let label = segment.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(" ");
+            let terminal = if self.nodes[*target].is_terminal { "." } else { "" };
+            out.push_str(&format!("{}{} {}{} #{}\n", prefix, branch, label, terminal, target));
+            let child_prefix = format!("{}{}", prefix, if is_last { "  " } else { "│ " });
+            self.format_tree_recursive(*target, &child_prefix, out);
         }
+    }
 
-        merged_node.is_terminal = any_terminal;
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
 
-        // Now group all grandchildren by their segment lengths
-        let mut length_groups: HashMap<usize, BTreeSet<Vec<T>>> = HashMap::new();
-        
-        for (segment, _) in &all_grandchildren {
-            let segment_length = segment.len();
-            length_groups.entry(segment_length)
-                .or_insert_with(BTreeSet::new)
-                .insert(segment.clone());
+    pub fn edge_count(&self) -> usize {
+        self.nodes.iter().map(|node| node.edges.len()).sum()
+    }
+
+    // Every edge label in the DAG; a shared suffix appears once
+    pub fn edge_labels(&self) -> Vec<Vec<T>> {
+        self.nodes.iter()
+            .flat_map(|node| node.edges.iter().map(|(segment, _)| segment.clone()))
+            .collect()
+    }
+
+    pub fn contains(&self, sequence: &[T]) -> bool {
+        self.contains_from(self.root, sequence)
+    }
+
+    fn contains_from(&self, id: usize, sequence: &[T]) -> bool {
+        let node = &self.nodes[id];
+        if sequence.is_empty() {
+            return node.is_terminal;
         }
+        node.edges.iter()
+            .filter(|(segment, _)| !segment.is_empty() && sequence.starts_with(segment))
+            .any(|(segment, target)| self.contains_from(*target, &sequence[segment.len()..]))
+    }
+}
 
-        // Process each length group recursively
-        for (length, segment_set) in length_groups {
-            let group_key = LengthGroupKey::new(length, segment_set.clone());
-            
-            // Recursively merge this length group
-            let merged_child = Self::merge_children_by_length_group(
-                &all_grandchildren,
-                &segment_set,
-            );
-            
-            merged_node.children.insert(group_key, merged_child);
+// Aho-Corasick automaton built from a trie. Multi-element segments are
+// expanded into one state per element; state 0 is the root.
+#[derive(Debug, Clone)]
+pub struct PatternAutomaton<T> {
+    states: Vec<AutomatonState<T>>,
+    patterns: Vec<Vec<T>>,
+}
+
+#[derive(Debug, Clone)]
+struct AutomatonState<T> {
+    next: HashMap<T, usize>,
+    fail: usize,
+    // Indices into `patterns` of every stored sequence ending at this state
+    outputs: Vec<usize>,
+}
+
+impl<T> PatternAutomaton<T>
+where
+    T: Clone + Eq + Hash + Debug + Display,
+{
+    fn from_trie(trie: &TrieNode<T>) -> Self {
+        let mut automaton = PatternAutomaton {
+            states: vec![AutomatonState { next: HashMap::new(), fail: 0, outputs: Vec::new() }],
+            patterns: Vec::new(),
+        };
+        if trie.is_terminal {
+            automaton.patterns.push(Vec::new());
+            automaton.states[0].outputs.push(0);
         }
+        automaton.expand(trie, 0, &mut Vec::new());
+        automaton.link_failures();
+        automaton
+    }
 
-        merged_node
+    // Add one state per element below `node`, recording terminals as outputs
+    fn expand(&mut self, node: &TrieNode<T>, state: usize, path: &mut Vec<T>) {
+        for (segment, child) in &node.children {
+            let mut current = state;
+            for element in segment {
+                current = match self.states[current].next.get(element) {
+                    Some(&existing) => existing,
+                    None => {
+                        let id = self.states.len();
+                        self.states.push(AutomatonState { next: HashMap::new(), fail: 0, outputs: Vec::new() });
+                        self.states[current].next.insert(element.clone(), id);
+                        id
+                    }
+                };
+            }
+
+            path.extend_from_slice(segment);
+            if child.is_terminal {
+                self.states[current].outputs.push(self.patterns.len());
+                self.patterns.push(path.clone());
+            }
+            self.expand(child, current, path);
+            path.truncate(path.len() - segment.len());
+        }
     }
 
-    // Original print method (kept for backward compatibility)
-    pub fn print(&self, depth: usize) {
-        let indent = "  ".repeat(depth);
-        
-        if self.is_terminal {
-            println!("{}Terminal", indent);
+    // Breadth-first computation of failure links and inherited outputs
+    fn link_failures(&mut self) {
+        let mut queue: std::collections::VecDeque<usize> = self.states[0].next.values().copied().collect();
+
+        while let Some(state) = queue.pop_front() {
+            let transitions: Vec<(T, usize)> = self.states[state].next.iter()
+                .map(|(element, &target)| (element.clone(), target))
+                .collect();
+            for (element, target) in transitions {
+                let mut fallback = self.states[state].fail;
+                let fail = loop {
+                    if let Some(&candidate) = self.states[fallback].next.get(&element) {
+                        break candidate;
+                    }
+                    if fallback == 0 {
+                        break 0;
+                    }
+                    fallback = self.states[fallback].fail;
+                };
+                self.states[target].fail = fail;
+                let inherited = self.states[fail].outputs.clone();
+                self.states[target].outputs.extend(inherited);
+                queue.push_back(target);
+            }
         }
+    }
 
-        for (key, child) in &self.children {
-            let segments: Vec<Vec<T>> = key.segments.iter().cloned().collect();
-            println!("{}({}, {:?}):", indent, key.length, segments);
-            child.print(depth + 1);
+    // Every occurrence of a stored sequence in `text`, as (index of the match's
+    // last element, matched sequence), ordered by end position. The empty
+    // sequence is never reported.
+    pub fn find_all(&self, text: &[T]) -> Vec<(usize, Vec<T>)> {
+        let mut matches = Vec::new();
+        let mut state = 0;
+
+        for (position, element) in text.iter().enumerate() {
+            loop {
+                if let Some(&next) = self.states[state].next.get(element) {
+                    state = next;
+                    break;
+                }
+                if state == 0 {
+                    break;
+                }
+                state = self.states[state].fail;
+            }
+            for &pattern in &self.states[state].outputs {
+                if !self.patterns[pattern].is_empty() {
+                    matches.push((position, self.patterns[pattern].clone()));
+                }
+            }
         }
+        matches
     }
+}
 
-    // Tree visualization method for length-grouped trie
-    pub fn print_tree(&self) {
-        self.print_tree_with_options(" ", ".");
+// Structural equality: same segments, terminals and frequencies (cached stats are ignored)
+impl<T: Eq + Hash> PartialEq for TrieNode<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.is_terminal == other.is_terminal
+            && self.frequency == other.frequency
+            && self.children == other.children
     }
+}
 
-    pub fn print_tree_with_options(&self, separator: &str, terminal_char: &str) {
-        println!("Root{}", if self.is_terminal { terminal_char } else { "" });
-        self.print_tree_recursive("", true, separator, terminal_char);
+impl<T: Eq + Hash> Eq for TrieNode<T> {}
+
+// Consistent with the structural equality above. Children live in a HashMap
+// whose iteration order varies between equal tries, so each child entry is
+// hashed on its own and the results are combined with a commutative sum;
+// this makes the hash independent of child order.
+impl<T: Eq + Hash> Hash for TrieNode<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        use std::hash::Hasher;
+
+        self.is_terminal.hash(state);
+        self.frequency.hash(state);
+        let children = self.children.iter()
+            .map(|entry| {
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                entry.hash(&mut hasher);
+                hasher.finish()
+            })
+            .fold(0u64, u64::wrapping_add);
+        self.children.len().hash(state);
+        children.hash(state);
     }
+}
 
-    fn print_tree_recursive(&self, prefix: &str, is_last_sibling: bool, separator: &str, terminal_char: &str) {
-        let mut children: Vec<_> = self.children.iter().collect();
-        // Sort by length first, then by segments for consistent output
-        children.sort_by(|a, b| {
-            a.0.length.cmp(&b.0.length)
-                .then_with(|| a.0.segments.cmp(&b.0.segments))
-        });
-        
-        for (i, (key, child)) in children.iter().enumerate() {
-            let is_last = i == children.len() - 1;
-            let branch = if is_last { "└─" } else { "├─" };
-            let child_prefix = if is_last { "  " } else { "│ " };
-            
-            // Format the segments more compactly
-            let segments_display = if key.segments.len() == 1 {
-                Self::format_segment(key.segments.iter().next().unwrap(), separator)
-            } else {
-                format!("[{}]", 
-                    key.segments.iter()
-                        .map(|seg| Self::format_segment(seg, separator))
-                        .collect::<Vec<_>>()
-                        .join(", "))
-            };
-            
-            println!("{}{}len={} {}{}", 
-                prefix, branch, key.length, segments_display,
-                if child.is_terminal { terminal_char } else { "" });
-            
-            child.print_tree_recursive(&format!("{}{}", prefix, child_prefix), is_last, separator, terminal_char);
+// Iterates stored sequences in unspecified order, so `for seq in &trie { ... }` works
+impl<T> IntoIterator for &TrieNode<T>
+where
+    T: Clone + Eq + Hash + Debug + Display,
+{
+    type Item = Vec<T>;
+    type IntoIter = SequenceIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_sequences()
+    }
+}
+
+// Owning iterator over the sequences stored in a trie, as returned by
+// `TrieNode::iter_sequences`
+#[derive(Debug, Clone)]
+pub struct SequenceIter<T> {
+    inner: std::vec::IntoIter<Vec<T>>,
+}
+
+impl<T> Iterator for SequenceIter<T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Vec<T>> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for SequenceIter<T> {}
+
+// Position inside a trie that moves down one segment at a time, as returned
+// by `TrieNode::cursor`
+#[derive(Debug, Clone)]
+pub struct TrieCursor<'a, T> {
+    node: &'a TrieNode<T>,
+    path: Vec<T>,
+}
+
+impl<'a, T> TrieCursor<'a, T>
+where
+    T: Clone + Eq + Hash + Debug + Display,
+{
+    // Move to the child reached by exactly `segment`; returns false (and
+    // stays put) if there is no such child
+    pub fn descend(&mut self, segment: &[T]) -> bool {
+        match self.node.children.get(segment) {
+            Some(child) => {
+                self.node = child;
+                self.path.extend_from_slice(segment);
+                true
+            }
+            None => false,
         }
     }
 
-    fn format_segment(segment: &[T], separator: &str) -> String {
-        segment.iter()
-            .map(|item| format!("{:?}", item))
-            .collect::<Vec<_>>()
-            .join(separator)
+    pub fn is_terminal(&self) -> bool {
+        self.node.is_terminal
+    }
+
+    // Segment keys of the current node's children, in unspecified order
+    pub fn segments(&self) -> Vec<&'a [T]> {
+        self.node.children.keys().map(|segment| &segment[..]).collect()
+    }
+
+    // Flattened path from the root to the current node
+    pub fn current_path(&self) -> Vec<T> {
+        self.path.clone()
+    }
+
+    // The subtrie at the current position
+    pub fn node(&self) -> &'a TrieNode<T> {
+        self.node
+    }
+}
+
+// Shared handle to one trie for many query threads. Clones share the same
+// trie. Readers take a read lock for the duration of a single query, so
+// queries run in parallel; `insert` takes the write lock and is applied
+// atomically, so readers see the trie either before or after an insert,
+// never in between.
+#[derive(Debug)]
+pub struct ConcurrentTrie<T> {
+    inner: Arc<RwLock<TrieNode<T>>>,
+}
+
+impl<T> Clone for ConcurrentTrie<T> {
+    fn clone(&self) -> Self {
+        ConcurrentTrie { inner: Arc::clone(&self.inner) }
+    }
+}
+
+impl<T> ConcurrentTrie<T>
+where
+    T: Clone + Eq + Hash + Debug + Display,
+{
+    pub fn new(trie: TrieNode<T>) -> Self {
+        ConcurrentTrie { inner: Arc::new(RwLock::new(trie)) }
+    }
+
+    pub fn contains(&self, sequence: &[T]) -> bool {
+        self.inner.read().expect("trie lock poisoned").contains(sequence)
+    }
+
+    pub fn completions(&self, prefix: &[T]) -> Vec<Vec<T>> {
+        self.inner.read().expect("trie lock poisoned").completions(prefix)
+    }
+
+    pub fn insert(&self, sequence: &[T]) -> bool {
+        self.inner.write().expect("trie lock poisoned").insert(sequence)
+    }
+
+    // A copy of the trie as of now, unaffected by later inserts
+    pub fn snapshot(&self) -> TrieNode<T> {
+        self.inner.read().expect("trie lock poisoned").clone()
+    }
+}
+
+// Render a string as a YAML flow scalar, double-quoting it when a plain
+// scalar would be misread (special leading characters, separators, keywords)
+fn yaml_scalar(value: &str) -> String {
+    const SPECIAL_START: &[char] = &[
+        '-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '\'', '"', '%', '@', '`',
+    ];
+    let keyword = matches!(
+        value.to_ascii_lowercase().as_str(),
+        "true" | "false" | "yes" | "no" | "on" | "off" | "null" | "~"
+    );
+    let needs_quotes = value.is_empty()
+        || keyword
+        || value.parse::<f64>().is_ok()
+        || value.starts_with(SPECIAL_START)
+        || value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(|c: char| c.is_control() || matches!(c, ':' | '#' | ',' | '[' | ']' | '{' | '}'));
+    if needs_quotes {
+        json_string(value)
+    } else {
+        value.to_string()
     }
 }
 
 // Helper functions for string-based examples (backward compatibility)
 impl TrieNode<char> {
+    // An empty word is the empty sequence: it marks the root terminal
     pub fn from_words(words: &[&str]) -> Self {
         let char_sequences: Vec<Vec<char>> = words.iter()
             .map(|word| word.chars().collect())
@@ -520,4 +3973,201 @@ impl TrieNode<char> {
             .collect();
         Self::from_sequences(&sequences)
     }
-}
\ No newline at end of file
+
+    // Inverse of pattern extraction: every `Any` in a pattern is expanded
+    // over `alphabet` and the resulting words are built into one trie.
+    // Expansion stops with an error once more than `max_expansions` words
+    // would be produced across all patterns, or when a pattern uses `Any`
+    // with an empty alphabet (it would otherwise match nothing).
+    pub fn from_patterns(
+        patterns: &[Vec<PatternElement<char>>],
+        alphabet: &[char],
+        max_expansions: usize,
+    ) -> Result<TrieNode<char>, BuildError> {
+        let too_many = BuildError::TooManyExpansions { limit: max_expansions };
+        let mut words: Vec<Vec<char>> = Vec::new();
+        for pattern in patterns {
+            let mut expanded: Vec<Vec<char>> = vec![Vec::new()];
+            for element in pattern {
+                expanded = match element {
+                    PatternElement::Exact(c) => {
+                        expanded.into_iter().map(|mut word| { word.push(*c); word }).collect()
+                    }
+                    PatternElement::Any => {
+                        if alphabet.is_empty() {
+                            return Err(BuildError::EmptyAlphabet);
+                        }
+                        if expanded.len().saturating_mul(alphabet.len()) > max_expansions {
+                            return Err(too_many);
+                        }
+                        expanded.iter()
+                            .flat_map(|word| alphabet.iter().map(move |c| {
+                                let mut word = word.clone();
+                                word.push(*c);
+                                word
+                            }))
+                            .collect()
+                    }
+                };
+            }
+            words.extend(expanded);
+            if words.len() > max_expansions {
+                return Err(too_many);
+            }
+        }
+
+        let sequences: Vec<&[char]> = words.iter().map(|word| word.as_slice()).collect();
+        Ok(Self::from_sequences(&sequences))
+    }
+
+    // Parse the indentation-based format written by `to_trie_string`
+    pub fn from_trie_string(s: &str) -> Result<TrieNode<char>, ParseError> {
+        // Nodes along the current path, each with the segment leading to it
+        let mut stack: Vec<(Vec<char>, TrieNode<char>)> = vec![(Vec::new(), TrieNode::new())];
+
+        for (index, line) in s.lines().enumerate() {
+            let line_no = index + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let indent = line.len() - line.trim_start_matches(' ').len();
+            if indent % 2 != 0 {
+                return Err(ParseError::BadIndent { line: line_no });
+            }
+            let depth = indent / 2;
+            let (segment, frequency) = Self::parse_trie_line(&line[indent..], line_no)?;
+
+            if segment.is_empty() {
+                // A bare terminal marker on the first line marks the root as terminal
+                if depth != 0 || stack.len() != 1 || frequency == 0 {
+                    return Err(ParseError::EmptySegment { line: line_no });
+                }
+                stack[0].1.is_terminal = true;
+                stack[0].1.frequency = frequency;
+                continue;
+            }
+            if depth >= stack.len() {
+                return Err(ParseError::BadIndent { line: line_no });
+            }
+            while stack.len() > depth + 1 {
+                Self::attach_top(&mut stack, line_no)?;
+            }
+
+            let mut node = TrieNode::new();
+            node.is_terminal = frequency > 0;
+            node.frequency = frequency;
+            stack.push((segment, node));
+        }
+
+        let last_line = s.lines().count();
+        while stack.len() > 1 {
+            Self::attach_top(&mut stack, last_line)?;
+        }
+        let (_, mut root) = stack.pop().unwrap();
+        root.refresh_cached_stats();
+        Ok(root)
+    }
+
+    fn attach_top(stack: &mut Vec<(Vec<char>, TrieNode<char>)>, line_no: usize) -> Result<(), ParseError> {
+        let (segment, node) = stack.pop().unwrap();
+        let parent = &mut stack.last_mut().unwrap().1;
+        if parent.children.insert(segment.into(), node).is_some() {
+            return Err(ParseError::DuplicateSegment { line: line_no });
+        }
+        Ok(())
+    }
+
+    // Split a line body into its unescaped segment and terminal frequency (0 if not terminal)
+    fn parse_trie_line(body: &str, line_no: usize) -> Result<(Vec<char>, usize), ParseError> {
+        let mut segment = Vec::new();
+        let mut chars = body.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => segment.push(match chars.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some(c @ ('\\' | '*' | ' ')) => c,
+                    _ => return Err(ParseError::BadEscape { line: line_no }),
+                }),
+                '*' => {
+                    let count: String = chars.collect();
+                    let frequency = if count.is_empty() {
+                        1
+                    } else {
+                        count.parse().map_err(|_| ParseError::BadFrequency { line: line_no })?
+                    };
+                    return Ok((segment, frequency));
+                }
+                c => segment.push(c),
+            }
+        }
+        Ok((segment, 0))
+    }
+}
+
+// Tries over borrowed elements: segments hold `&T`, so building never clones
+// a `T`. The trie borrows from the input and cannot outlive it.
+impl<'a, T> TrieNode<&'a T>
+where
+    T: Eq + Hash + Debug + Display,
+{
+    pub fn from_sequences_borrowed(sequences: &[&'a [T]]) -> Self {
+        let borrowed: Vec<Vec<&'a T>> = sequences.iter()
+            .map(|seq| seq.iter().collect())
+            .collect();
+        let slices: Vec<&[&'a T]> = borrowed.iter().map(|seq| seq.as_slice()).collect();
+        Self::from_sequences(&slices)
+    }
+}
+
+// Token clean-up applied by `TrieNode::from_sentences` before insertion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WordTrieOptions {
+    // Drop tokens that are empty (after trimming, if enabled) or whitespace-only
+    pub skip_empty_tokens: bool,
+    // Strip leading and trailing whitespace from every token
+    pub trim_tokens: bool,
+}
+
+impl TrieNode<String> {
+    // Build a word trie from tokenized sentences, cleaning tokens per `options`
+    pub fn from_sentences(sentences: &[&[&str]], options: WordTrieOptions) -> Self {
+        let token_sequences: Vec<Vec<String>> = sentences.iter()
+            .map(|sentence| {
+                sentence.iter()
+                    .map(|token| if options.trim_tokens { token.trim() } else { token })
+                    .filter(|token| !(options.skip_empty_tokens && token.trim().is_empty()))
+                    .map(|token| token.to_string())
+                    .collect()
+            })
+            .collect();
+        let sequences: Vec<&[String]> = token_sequences.iter()
+            .map(|seq| seq.as_slice())
+            .collect();
+        Self::from_sequences(&sequences)
+    }
+}
+
+// Error raised by `TrieNode::from_trie_string`, with 1-based line numbers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    BadIndent { line: usize },
+    BadEscape { line: usize },
+    BadFrequency { line: usize },
+    EmptySegment { line: usize },
+    DuplicateSegment { line: usize },
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::BadIndent { line } => write!(f, "line {}: indentation does not match the tree depth", line),
+            ParseError::BadEscape { line } => write!(f, "line {}: unknown escape sequence", line),
+            ParseError::BadFrequency { line } => write!(f, "line {}: invalid count after '*'", line),
+            ParseError::EmptySegment { line } => write!(f, "line {}: empty segment", line),
+            ParseError::DuplicateSegment { line } => write!(f, "line {}: duplicate sibling segment", line),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
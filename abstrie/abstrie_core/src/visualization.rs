@@ -0,0 +1,51 @@
+// Prefix-tree views of a trie, ported from the reference `get_prefixes_dict`
+// and `print_tree` helpers. All maps are ordered, so output is deterministic.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+// Nested map of branching prefixes: each key maps to the branching
+// prefixes found below it, with leaves holding an empty map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixTree<K: Ord> {
+    pub children: BTreeMap<K, PrefixTree<K>>,
+}
+
+impl<K: Ord> Default for PrefixTree<K> {
+    fn default() -> Self {
+        PrefixTree { children: BTreeMap::new() }
+    }
+}
+
+impl<K: Ord + Clone> PrefixTree<K> {
+    // Union of both trees, merging the subtrees of equal keys
+    pub fn merge(&mut self, other: PrefixTree<K>) {
+        for (key, subtree) in other.children {
+            self.children.entry(key).or_default().merge(subtree);
+        }
+    }
+}
+
+// Render a prefix tree with box-drawing branches. Siblings are ordered by
+// prefix length, then by content.
+pub fn print_prefix_tree<T: Ord + Display>(tree: &PrefixTree<Vec<T>>, separator: &str) -> String {
+    let mut out = String::new();
+    write_prefix_tree(tree, separator, "", &mut out);
+    out
+}
+
+fn write_prefix_tree<T: Ord + Display>(tree: &PrefixTree<Vec<T>>, separator: &str, indent: &str, out: &mut String) {
+    let mut children: Vec<_> = tree.children.iter().collect();
+    // BTreeMap order is by content; the stable sort keeps it within a length
+    children.sort_by_key(|(prefix, _)| prefix.len());
+
+    for (i, (prefix, subtree)) in children.iter().enumerate() {
+        let is_last = i == children.len() - 1;
+        let branch = if is_last { "└──" } else { "├──" };
+        let label = prefix.iter().map(|item| item.to_string()).collect::<Vec<_>>().join(separator);
+        out.push_str(&format!("{}{} {}\n", indent, branch, label));
+
+        let child_indent = format!("{}{}", indent, if is_last { "   " } else { "│  " });
+        write_prefix_tree(subtree, separator, &child_indent, out);
+    }
+}
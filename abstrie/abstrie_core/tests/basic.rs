@@ -1,6 +1,6 @@
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use abstrie_core::*;
 
     #[test]
     fn test_generic_char_trie() {
@@ -9,7 +9,7 @@ mod tests {
         let length_grouped = LengthGroupedNode::from_trie(&trie);
         
         // Should have 2 main groups: length 2 and length 3
-        assert_eq!(length_grouped.children.len(), 2);
+        assert_eq!(length_grouped.num_children(), 2);
     }
 
     #[test]
@@ -24,7 +24,7 @@ mod tests {
         let length_grouped = LengthGroupedNode::from_trie(&trie);
         
         // Should have groups based on word count
-        assert!(!length_grouped.children.is_empty());
+        assert!(length_grouped.num_children() > 0);
     }
 
     #[test]
@@ -39,7 +39,7 @@ mod tests {
         let length_grouped = LengthGroupedNode::from_trie(&trie);
         
         // Should create proper groupings
-        assert!(!length_grouped.children.is_empty());
+        assert!(length_grouped.num_children() > 0);
     }
 
     #[test]
@@ -55,4 +55,1685 @@ mod tests {
         length_grouped.print_tree();
         length_grouped.print_tree_with_options("|", "#");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_write_tree_matches_format_tree() {
+        let words = vec!["ape", "app", "application", "bat"];
+        let trie = TrieNode::from_words(&words);
+
+        let mut out = String::new();
+        trie.write_tree(&mut out, " ", ".", false).unwrap();
+
+        assert_eq!(out, trie.format_tree(" ", ".", false));
+        assert!(out.starts_with("Root\n"));
+    }
+
+    #[test]
+    fn test_compression_ratio() {
+        let redundant = TrieNode::from_words(&["abcd", "abce", "abcf"]);
+        assert!(redundant.compression_ratio() > 1.0);
+
+        let distinct = TrieNode::from_words(&["ab", "cd"]);
+        assert!((distinct.compression_ratio() - 1.0).abs() < 1e-9);
+
+        // Duplicates count once, so the ratio ignores insertion counts
+        let duplicated = TrieNode::from_words(&["ab", "ab", "cd"]);
+        assert!((duplicated.compression_ratio() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_segmented_build_marks_leaves_terminal() {
+        let trie = TrieNode::from_words(&["ab", "cd"]);
+        assert!(trie.contains(&['a', 'b']));
+        assert!(trie.contains(&['c', 'd']));
+        assert_eq!(trie.stats().terminal_count, 2);
+
+        let (nodes, _) = trie.to_edges();
+        assert!(nodes.iter().filter(|node| node.id != 0).all(|node| node.is_terminal && node.frequency == 1));
+    }
+
+    #[test]
+    fn test_terminal_and_children_accessors() {
+        let trie = TrieNode::from_words(&["", "cat", "car", "dog"]);
+        assert!(trie.is_terminal());
+        assert_eq!(trie.num_children(), 2);
+
+        let mut grouped = LengthGroupedNode::from_trie(&trie);
+        assert!(grouped.is_terminal());
+        assert_eq!(grouped.num_children(), 2);
+
+        grouped.set_terminal(false);
+        assert!(!grouped.is_terminal());
+    }
+
+    #[test]
+    fn test_from_weighted() {
+        let items = vec![
+            ("cat".chars().collect::<Vec<_>>(), 3),
+            ("car".chars().collect(), 5),
+            ("dog".chars().collect(), 1),
+        ];
+        let trie = TrieNode::from_weighted(items);
+
+        assert_eq!(trie.frequency(&['c', 'a', 't']), 3);
+        assert_eq!(trie.frequency(&['c', 'a', 'r']), 5);
+        assert_eq!(trie.frequency(&['d', 'o', 'g']), 1);
+        assert_eq!(trie.frequency(&['c', 'a']), 0);
+        assert_eq!(trie.count_sequences(), 3);
+    }
+
+    #[test]
+    fn test_abstract_pattern() {
+        let trie = TrieNode::from_words(&["cat", "cot", "cut"]);
+        assert_eq!(
+            trie.abstract_pattern(&[]),
+            vec![PatternElement::Exact('c'), PatternElement::Any, PatternElement::Exact('t')]
+        );
+    }
+
+    #[test]
+    fn test_resegment() {
+        let words = vec!["abcdefghij", "abcdefghijkl", "x"];
+        let mut trie = TrieNode::from_words(&words);
+        assert_eq!(trie.longest_segment_len(), 10);
+
+        trie.resegment(3);
+        assert!(trie.longest_segment_len() <= 3);
+        for word in &words {
+            assert!(trie.contains(&word.chars().collect::<Vec<_>>()));
+        }
+        assert!(!trie.contains(&['a', 'b', 'c']));
+        assert_eq!(trie.count_sequences(), 3);
+    }
+
+    #[test]
+    fn test_to_edges() {
+        let trie = TrieNode::from_words(&["cat", "car", "dog"]);
+        let (nodes, edges) = trie.to_edges();
+
+        // Root, "ca", "t", "r", "dog"
+        assert_eq!(nodes.len(), 5);
+        assert_eq!(edges.len(), nodes.len() - 1);
+        assert_eq!(nodes.iter().filter(|node| node.is_terminal).count(), 3);
+        for (parent, child, segment) in &edges {
+            assert!(*parent < nodes.len() && *child < nodes.len());
+            assert!(!segment.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_format_tree_sorted_by() {
+        let trie = TrieNode::from_words(&["apple", "mango", "zebra"]);
+
+        let ascending = trie.format_tree_sorted_by("", ".", false, |a, b| a.cmp(b));
+        assert_eq!(ascending, "Root\n├─ apple.\n├─ mango.\n└─ zebra.\n");
+
+        let descending = trie.format_tree_sorted_by("", ".", false, |a, b| b.cmp(a));
+        assert_eq!(descending, "Root\n├─ zebra.\n├─ mango.\n└─ apple.\n");
+    }
+
+    #[test]
+    fn test_terminal_depths() {
+        let trie = TrieNode::from_words(&["a", "application"]);
+        assert_eq!(trie.terminal_depths(), vec![1, 2]);
+
+        let trie = TrieNode::from_words(&["ape", "app", "application", "bat"]);
+        let depths = trie.terminal_depths();
+        assert_eq!(depths, vec![1, 2, 2, 3]);
+        assert_eq!(depths.len(), trie.count_sequences());
+    }
+
+    #[test]
+    fn test_display_collisions_fall_back_to_debug() {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        struct Token {
+            text: &'static str,
+            kind: u8,
+        }
+
+        impl std::fmt::Display for Token {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.text)
+            }
+        }
+
+        let noun = [Token { text: "run", kind: 0 }];
+        let verb = [Token { text: "run", kind: 1 }];
+        let sequences = vec![&noun[..], &verb[..]];
+        let trie = TrieNode::from_sequences(&sequences);
+
+        let output = trie.format_tree(" ", ".", false);
+        assert!(output.contains("kind: 0"));
+        assert!(output.contains("kind: 1"));
+    }
+
+    #[test]
+    fn test_from_sequences_indexed() {
+        let sequences = vec![&[1, 2][..], &[3][..], &[1, 2][..], &[1][..]];
+        let (trie, indices) = TrieNode::from_sequences_indexed(&sequences);
+
+        assert_eq!(indices.len(), trie.count_sequences());
+        assert_eq!(indices[&vec![1, 2]], vec![0, 2]);
+        assert_eq!(indices[&vec![3]], vec![1]);
+        assert_eq!(indices[&vec![1]], vec![3]);
+    }
+
+    #[test]
+    fn test_extract_subtrie() {
+        let trie = TrieNode::from_words(&["app", "apple", "application", "apply", "banana"]);
+        let subtrie = trie.extract_subtrie(&['a', 'p', 'p']).unwrap();
+
+        assert!(subtrie.is_terminal());
+        for suffix in ["le", "lication", "ly"] {
+            assert!(subtrie.contains(&suffix.chars().collect::<Vec<_>>()));
+        }
+        assert_eq!(subtrie.count_sequences(), 4);
+        assert!(trie.extract_subtrie(&['x']).is_none());
+    }
+
+    #[test]
+    fn test_length_grouped_to_json() {
+        let trie = TrieNode::from_words(&["cat", "car", "dog"]);
+        let grouped = LengthGroupedNode::from_trie(&trie);
+        let json: serde_json::Value = serde_json::from_str(&grouped.to_json()).unwrap();
+
+        assert_eq!(json["terminal"], false);
+        let groups = json["children"].as_array().unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0]["length"], 2);
+        assert_eq!(groups[0]["segments"], serde_json::json!([["c", "a"]]));
+        assert_eq!(groups[1]["length"], 3);
+        assert_eq!(groups[1]["segments"], serde_json::json!([["d", "o", "g"]]));
+    }
+
+    #[test]
+    fn test_cached_stats_follow_insert_and_remove() {
+        let mut trie = TrieNode::from_words(&["cat", "car"]);
+        let check = |trie: &TrieNode<char>| {
+            let full = trie.stats();
+            let cached = trie.cached_stats();
+            assert_eq!(cached.node_count, full.node_count);
+            assert_eq!(cached.terminal_count, full.terminal_count);
+        };
+        check(&trie);
+
+        assert!(trie.insert(&['c', 'o', 'w']));
+        check(&trie);
+        assert!(trie.insert(&['c', 'a']));
+        check(&trie);
+        assert!(!trie.insert(&['c', 'a', 't']));
+        check(&trie);
+        assert!(trie.remove(&['c', 'o', 'w']));
+        check(&trie);
+        assert!(trie.remove(&['c', 'a']));
+        check(&trie);
+        assert!(!trie.remove(&['d', 'o', 'g']));
+        check(&trie);
+
+        assert!(trie.contains(&['c', 'a', 't']));
+        assert_eq!(trie.frequency(&['c', 'a', 't']), 2);
+        assert_eq!(trie.cached_stats().terminal_count, 2);
+    }
+
+    #[test]
+    fn test_iterate_trie_reference() {
+        let words = vec!["ape", "app", "application", "bat"];
+        let trie = TrieNode::from_words(&words);
+
+        let mut stored = std::collections::HashSet::new();
+        for seq in &trie {
+            stored.insert(seq.into_iter().collect::<String>());
+        }
+        let expected: std::collections::HashSet<String> = words.iter().map(|w| w.to_string()).collect();
+        assert_eq!(stored, expected);
+    }
+
+    #[test]
+    fn test_common_ancestor_prefix() {
+        let trie = TrieNode::from_words(&["application", "apple", "banana"]);
+        let application: Vec<char> = "application".chars().collect();
+        let apple: Vec<char> = "apple".chars().collect();
+        let banana: Vec<char> = "banana".chars().collect();
+
+        assert_eq!(trie.common_ancestor_prefix(&application, &apple), Some(vec!['a', 'p', 'p', 'l']));
+        assert_eq!(trie.common_ancestor_prefix(&apple, &banana), Some(vec![]));
+        assert_eq!(trie.common_ancestor_prefix(&apple, &['a', 'p']), None);
+    }
+
+    #[test]
+    fn test_prefix_free() {
+        assert!(TrieNode::from_words(&["cat", "dog"]).is_prefix_free());
+
+        let mut trie = TrieNode::from_words(&["a", "ab"]);
+        assert!(!trie.is_prefix_free());
+
+        trie.make_prefix_free();
+        assert!(trie.is_prefix_free());
+        assert!(trie.contains(&['a', 'b']));
+        assert!(!trie.contains(&['a']));
+    }
+
+    #[test]
+    fn test_from_sequences_with_sentinel() {
+        let words: Vec<Vec<char>> = ["a", "ab", "abc"].iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = words.iter().map(|w| w.as_slice()).collect();
+        let trie = TrieNode::from_sequences_with_sentinel(&sequences, '$');
+
+        // Every terminal is now a leaf reached through the sentinel
+        assert!(trie.is_prefix_free());
+        assert_eq!(trie.count_sequences(), 3);
+        for seq in &trie {
+            assert_eq!(seq.last(), Some(&'$'));
+        }
+        assert!(trie.contains(&['a', 'b', '$']));
+        assert!(!trie.contains(&['a', 'b']));
+    }
+
+    #[test]
+    fn test_lazy_grouped_node() {
+        let words = vec!["ape", "app", "application", "bans", "bat", "banner", "pot", "potion"];
+        let trie = TrieNode::from_words(&words);
+        let lazy = LazyGroupedNode::new(&trie);
+        assert_eq!(lazy.expansions(), 0);
+
+        // Walk down the first branch only
+        let first = &lazy.children()[0].1;
+        first.children();
+        first.children();
+        assert_eq!(lazy.expansions(), 2);
+
+        let eager = LengthGroupedNode::from_trie(&trie);
+        assert_eq!(lazy.to_grouped().to_json(), eager.to_json());
+        assert!(lazy.expansions() > 2);
+    }
+
+    #[test]
+    fn test_trie_string_round_trip() {
+        let trie = TrieNode::from_words(&["", "ape", "app", "app", "application", "a*b", " x\\y", "bat"]);
+        let text = trie.to_trie_string();
+
+        let parsed = TrieNode::from_trie_string(&text).unwrap();
+        assert_eq!(parsed, trie);
+        assert_eq!(parsed.frequency(&['a', 'p', 'p']), 2);
+
+        assert_eq!(TrieNode::from_trie_string("ab\n     c"), Err(ParseError::BadIndent { line: 2 }));
+    }
+
+    #[test]
+    fn test_repeated_segments() {
+        let trie = TrieNode::from_words(&["cat", "cats", "dog", "dogs", "bird"]);
+        let repeated = trie.repeated_segments();
+
+        assert!(repeated[&vec!['s']] >= 2);
+        assert!(!repeated.contains_key(&vec!['b', 'i', 'r', 'd']));
+    }
+
+    #[test]
+    fn test_from_sentences_skips_empty_tokens() {
+        let sentences = vec![&["the", "", "dog"][..], &["the", " cat ", "  "][..]];
+        let options = WordTrieOptions { skip_empty_tokens: true, trim_tokens: true };
+        let trie = TrieNode::from_sentences(&sentences, options);
+
+        let the_dog = vec!["the".to_string(), "dog".to_string()];
+        let the_cat = vec!["the".to_string(), "cat".to_string()];
+        assert!(trie.contains(&the_dog));
+        assert!(trie.contains(&the_cat));
+        assert_eq!(trie.count_sequences(), 2);
+
+        let raw = TrieNode::from_sentences(&sentences, WordTrieOptions::default());
+        assert!(!raw.contains(&the_dog));
+    }
+
+    #[test]
+    fn test_to_vec_with_frequencies() {
+        let trie = TrieNode::from_words(&["dog", "cat", "dog", "car", "dog", "cat"]);
+        assert_eq!(
+            trie.to_vec_with_frequencies(),
+            vec![
+                (vec!['c', 'a', 'r'], 1),
+                (vec!['c', 'a', 't'], 2),
+                (vec!['d', 'o', 'g'], 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_ops() {
+        let mut old = TrieNode::from_words(&["cat", "car", "card", "dog", "dog"]);
+        let new = TrieNode::from_words(&["cat", "card", "dog", "cow"]);
+
+        let ops = old.diff_ops(&new);
+        assert!(ops.contains(&TrieOp::Remove(vec!['c', 'a', 'r'])));
+        assert!(ops.contains(&TrieOp::Insert(vec!['c', 'o', 'w'])));
+
+        old.apply_ops(&ops);
+        assert_eq!(old, new);
+        assert!(old.diff_ops(&new).is_empty());
+    }
+
+    #[test]
+    fn test_pattern_automaton_find_all() {
+        let trie = TrieNode::from_words(&["he", "she", "his", "hers"]);
+        let automaton = trie.build_automaton();
+        let text: Vec<char> = "ushers".chars().collect();
+
+        let mut found: Vec<(usize, String)> = automaton.find_all(&text)
+            .into_iter()
+            .map(|(end, seq)| (end, seq.into_iter().collect()))
+            .collect();
+        found.sort();
+        assert_eq!(
+            found,
+            vec![(3, "he".to_string()), (3, "she".to_string()), (5, "hers".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_to_yaml() {
+        fn depth(node: &serde_yaml::Value) -> usize {
+            node["children"].as_sequence().unwrap()
+                .iter()
+                .map(|child| 1 + depth(child))
+                .max()
+                .unwrap_or(0)
+        }
+
+        let trie = TrieNode::from_words(&["a:b", "a:c", "-x", "#", "yes"]);
+        let yaml: serde_yaml::Value = serde_yaml::from_str(&trie.to_yaml()).unwrap();
+
+        assert_eq!(yaml["terminal"], serde_yaml::Value::Bool(false));
+        assert_eq!(depth(&yaml), trie.stats().max_depth);
+        let first: Vec<&str> = yaml["children"].as_sequence().unwrap()
+            .iter()
+            .map(|child| child["segment"][0].as_str().unwrap())
+            .collect();
+        assert!(first.contains(&"#"));
+        assert!(first.contains(&"-"));
+    }
+
+    #[test]
+    fn test_format_tree_weighted() {
+        let items = vec![
+            ("cat".chars().collect::<Vec<_>>(), 2),
+            ("car".chars().collect(), 1),
+            ("dog".chars().collect(), 1),
+        ];
+        let trie = TrieNode::from_weighted(items);
+        let output = trie.format_tree_weighted();
+
+        assert!(output.contains("c a (0.75)"));
+        assert!(output.contains("d o g. (0.25)"));
+        assert!(output.contains("t. (0.67)"));
+        assert!(output.contains("r. (0.33)"));
+    }
+
+    #[test]
+    fn test_min_common_prefix_option() {
+        let words: Vec<Vec<char>> = ["ab1", "ab2"].iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = words.iter().map(|w| w.as_slice()).collect();
+
+        let merged = TrieNode::from_sequences_with_options(&sequences, &BuildOptions { min_common_prefix: 1, ..BuildOptions::default() });
+        assert!(merged.get_subtrie(&['a', 'b']).is_some());
+        assert!(merged.get_subtrie(&['a']).is_none());
+        assert_eq!(merged.stats().max_depth, 2);
+
+        let split = TrieNode::from_sequences_with_options(&sequences, &BuildOptions { min_common_prefix: 3, ..BuildOptions::default() });
+        assert!(split.get_subtrie(&['a']).is_some());
+        assert_eq!(split.stats().max_depth, 3);
+        assert!(split.contains(&['a', 'b', '1']) && split.contains(&['a', 'b', '2']));
+        assert_eq!(merged.rebuild(&BuildOptions { min_common_prefix: 3, ..BuildOptions::default() }), split);
+    }
+
+    #[test]
+    fn test_write_jsonl() {
+        let trie = TrieNode::from_words(&["cat", "car", "dog"]);
+        let mut buffer: Vec<u8> = Vec::new();
+        trie.write_jsonl(&mut buffer).unwrap();
+
+        let text = String::from_utf8(buffer).unwrap();
+        let nodes: Vec<serde_json::Value> = text.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(nodes.len(), trie.stats().node_count);
+        assert!(nodes[0]["parent"].is_null());
+        for (i, node) in nodes.iter().enumerate() {
+            assert_eq!(node["id"], i);
+            if i > 0 {
+                assert!(node["parent"].as_u64().unwrap() < i as u64);
+            }
+        }
+        assert_eq!(nodes.iter().filter(|node| node["terminal"] == true).count(), 3);
+    }
+
+    #[test]
+    fn test_length_grouped_equality() {
+        let forward = TrieNode::from_words(&["ape", "app", "bat", "pot", "potion"]);
+        let backward = TrieNode::from_words(&["potion", "pot", "bat", "app", "ape"]);
+        assert_eq!(LengthGroupedNode::from_trie(&forward), LengthGroupedNode::from_trie(&backward));
+
+        let cat = LengthGroupedNode::from_trie(&TrieNode::from_words(&["cat"]));
+        let dog = LengthGroupedNode::from_trie(&TrieNode::from_words(&["dog"]));
+        assert_ne!(cat, dog);
+    }
+
+    #[test]
+    fn test_retain_by_total_length() {
+        let mut trie = TrieNode::from_words(&["a", "abcde", "xyz"]);
+        trie.retain_by_total_length(|n| n >= 3);
+
+        assert!(!trie.contains(&['a']));
+        assert!(trie.contains(&['a', 'b', 'c', 'd', 'e']));
+        assert!(trie.contains(&['x', 'y', 'z']));
+        assert_eq!(trie.stats().node_count, 3);
+        assert_eq!(trie.cached_stats().terminal_count, 2);
+    }
+
+    #[test]
+    fn test_boxed_segment_storage() {
+        // Boxed slices drop the Vec capacity word per segment key
+        assert!(std::mem::size_of::<Segment<char>>() < std::mem::size_of::<Vec<char>>());
+
+        let words = vec!["ape", "app", "application", "bat"];
+        let mut trie = TrieNode::from_words(&words);
+        trie.insert(&['a', 'p', 'p', 'l', 'e']);
+        for word in words.iter().chain(["apple"].iter()) {
+            assert!(trie.contains(&word.chars().collect::<Vec<_>>()));
+        }
+        assert_eq!(trie.iter_sequences().count(), 5);
+    }
+
+    #[test]
+    fn test_length_grouped_with_children() {
+        use std::collections::{BTreeSet, HashMap};
+
+        let key = |length: usize, segments: &[&str]| {
+            let segments: BTreeSet<Vec<char>> = segments.iter().map(|s| s.chars().collect()).collect();
+            LengthGroupKey::new(length, segments)
+        };
+        let leaf = LengthGroupedNode::with_children(true, HashMap::new());
+        let middle = LengthGroupedNode::with_children(false, HashMap::from([(key(1, &["r", "t"]), leaf)]));
+        let expected = LengthGroupedNode::with_children(false, HashMap::from([(key(2, &["ca"]), middle)]));
+
+        let grouped = LengthGroupedNode::from_trie(&TrieNode::from_words(&["cat", "car"]));
+        assert_eq!(grouped, expected);
+        assert_eq!(grouped.children().len(), 1);
+    }
+
+    #[test]
+    fn test_factor_common_suffixes() {
+        let trie = TrieNode::from_words(&["running", "jumping", "walk"]);
+        let dag = trie.factor_common_suffixes();
+
+        let labels: Vec<String> = dag.edge_labels().into_iter().map(|l| l.into_iter().collect()).collect();
+        assert_eq!(labels.iter().filter(|label| label.as_str() == "ing").count(), 1);
+        assert!(labels.contains(&"runn".to_string()) && labels.contains(&"jump".to_string()));
+
+        for word in ["running", "jumping", "walk"] {
+            assert!(dag.contains(&word.chars().collect::<Vec<_>>()));
+        }
+        assert!(!dag.contains(&['r', 'u', 'n', 'n']));
+    }
+
+    #[test]
+    fn test_contains_within_depth() {
+        let mut trie = TrieNode::new();
+        let short = ['a', 'b'];
+        let long = ['a', 'b', 'c', 'd'];
+        trie.insert(&short);
+        trie.insert(&['a', 'b', 'c']);
+        trie.insert(&long);
+
+        assert_eq!(trie.contains_within_depth(&short, 1), Some(true));
+        assert_eq!(trie.contains_within_depth(&['x'], 0), Some(false));
+        assert_eq!(trie.contains_within_depth(&long, 1), None);
+        assert_eq!(trie.contains_within_depth(&long, 3), Some(true));
+    }
+
+    #[test]
+    fn test_custom_segment_formatter() {
+        struct HexFormatter;
+        impl SegmentFormatter<u8> for HexFormatter {
+            fn format(&self, segment: &[u8]) -> String {
+                segment.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(":")
+            }
+        }
+
+        let mut trie = TrieNode::new();
+        trie.insert(&[0x0au8, 0xff]);
+        let output = trie.format_tree_with_formatter(".", &HexFormatter);
+        assert_eq!(output, "Root\n└─ 0a:ff.\n");
+
+        let display = trie.format_tree_with_formatter(".", &DisplayFormatter);
+        assert_eq!(display, "Root\n└─ 10 255.\n");
+    }
+
+    #[test]
+    fn test_extract_patterns() {
+        let trie = TrieNode::from_words(&["log1", "log2", "log3"]);
+        let patterns = trie.extract_patterns();
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(patterns[0].to_string(), "log*");
+        assert_eq!(patterns[0].wildcard_positions(), vec![3]);
+    }
+
+    #[test]
+    fn test_merge_normalized_differently_segmented() {
+        let words = ["station", "stationary", "stack"];
+        let mut coarse = TrieNode::from_words(&words);
+        coarse.resegment(3);
+        let mut fine = TrieNode::from_words(&words);
+        fine.resegment(2);
+
+        coarse.merge_normalized(&fine);
+
+        let (_, edges) = coarse.to_edges();
+        let mut siblings: Vec<(usize, char)> = edges.iter().map(|(parent, _, segment)| (*parent, segment[0])).collect();
+        let total = siblings.len();
+        siblings.sort();
+        siblings.dedup();
+        assert_eq!(siblings.len(), total);
+        for word in words {
+            let seq: Vec<char> = word.chars().collect();
+            assert!(coarse.contains(&seq));
+            assert_eq!(coarse.frequency(&seq), 2);
+        }
+    }
+
+    #[test]
+    fn test_spanned_offsets_round_trip() {
+        let first = [('l', 10), ('e', 11), ('t', 12)];
+        let second = [('l', 40), ('o', 42), ('g', 43)];
+        let spanned = TrieNode::from_sequences_spanned(&[&first, &second]);
+
+        assert!(spanned.trie().contains(&['l', 'e', 't']));
+        assert_eq!(spanned.spans_of(&['l', 'e', 't']), Some(vec![10, 11, 12]));
+        assert_eq!(spanned.spans_of(&['l', 'o', 'g']), Some(vec![40, 42, 43]));
+        assert_eq!(spanned.spans_of(&['l']), None);
+    }
+
+    #[test]
+    fn test_branching_entropy() {
+        let mut balanced = TrieNode::new();
+        for seq in [['a', 'a'], ['a', 'b'], ['b', 'a'], ['b', 'b']] {
+            balanced.insert(&seq);
+        }
+        assert!((balanced.branching_entropy() - 1.0).abs() < 1e-9);
+
+        let chain = TrieNode::from_words(&["abc"]);
+        assert_eq!(chain.branching_entropy(), 0.0);
+    }
+
+    #[test]
+    fn test_replace_subtrie() {
+        let mut trie = TrieNode::new();
+        for word in ["app", "apple", "apply", "bat"] {
+            trie.insert(&word.chars().collect::<Vec<_>>());
+        }
+        let replacement = TrieNode::from_words(&["", "le", "roach"]);
+        let prefix: Vec<char> = "app".chars().collect();
+        assert_eq!(trie.replace_subtrie(&prefix, replacement), Ok(()));
+
+        let has = |word: &str| trie.contains(&word.chars().collect::<Vec<_>>());
+        assert!(!has("apply"));
+        assert!(has("app") && has("apple") && has("approach") && has("bat"));
+        assert_eq!(trie.cached_stats().terminal_count, 4);
+
+        let mut trie = TrieNode::new();
+        trie.insert(&['a', 'p', 'p']);
+        assert_eq!(trie.replace_subtrie(&['a', 'p'], TrieNode::new()), Err(EditError::NotBoundaryAligned { matched: 0 }));
+        assert_eq!(trie.replace_subtrie(&['z'], TrieNode::new()), Err(EditError::PrefixNotFound));
+
+        // An empty replacement prunes the node; its parent's lone remaining
+        // link is re-joined, as `remove` would leave it
+        let mut trie = TrieNode::from_words(&["cat", "car", "dog"]);
+        assert_eq!(trie.replace_subtrie(&['c', 'a', 't'], TrieNode::new()), Ok(()));
+        let mut expected = TrieNode::from_words(&["cat", "car", "dog"]);
+        expected.remove(&['c', 'a', 't']);
+        assert_eq!(trie, expected);
+        assert!(trie.contains(&['c', 'a', 'r']) && !trie.contains(&['c', 'a', 't']));
+        assert_eq!(trie.stats().node_count, 3);
+    }
+
+    #[test]
+    fn test_prefix_counts() {
+        let trie = TrieNode::from_words(&["a", "ab", "abc"]);
+        let counts = trie.prefix_counts();
+        assert_eq!(counts.into_iter().collect::<Vec<_>>(), vec![(1, 3), (2, 2), (3, 1)]);
+        assert!(TrieNode::<char>::new().prefix_counts().is_empty());
+    }
+
+    #[test]
+    fn test_print_prefix_tree_is_deterministic() {
+        let words = ["apple", "app", "application", "banana", "bat", "batman", "banner", "banners"];
+        let render = || {
+            let mut trie = TrieNode::new();
+            for word in words {
+                trie.insert(&word.chars().collect::<Vec<_>>());
+            }
+            let (prefixes, lengths) = trie.get_prefixes_dict();
+            (print_prefix_tree(&prefixes, ""), lengths)
+        };
+
+        let (first, first_lengths) = render();
+        let (second, second_lengths) = render();
+        assert_eq!(first, second);
+        assert_eq!(first_lengths, second_lengths);
+        assert_eq!(first, "├── ba\n│  ├── ban\n│  │  └── banner\n│  └── bat\n└── app\n   └── appl\n");
+    }
+
+    #[test]
+    fn test_max_nodes_bound() {
+        let words: Vec<Vec<char>> = ["cat", "car", "dog", "dot"].iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = words.iter().map(|w| w.as_slice()).collect();
+
+        let tight = BuildOptions { max_nodes: 4, ..BuildOptions::default() };
+        assert_eq!(
+            TrieNode::try_from_sequences_with_options(&sequences, &tight),
+            Err(BuildError::TooLarge { max_nodes: 4 })
+        );
+
+        let roomy = BuildOptions { max_nodes: 16, ..BuildOptions::default() };
+        let trie = TrieNode::try_from_sequences_with_options(&sequences, &roomy).unwrap();
+        assert!(trie.stats().node_count <= 16);
+        assert!(trie.contains(&['d', 'o', 't']));
+    }
+
+    #[test]
+    fn test_leaf_segments() {
+        let trie = TrieNode::from_words(&["app", "apple", "apply"]);
+        let mut leaves: Vec<String> = trie.leaf_segments().into_iter().map(|s| s.into_iter().collect()).collect();
+        leaves.sort();
+        assert_eq!(leaves.len(), 2);
+        assert!(leaves[0].ends_with('e') && leaves[1].ends_with('y'));
+        assert!(!leaves.iter().any(|leaf| leaf.starts_with("app")));
+    }
+
+    #[test]
+    fn test_from_sequences_normalized() {
+        let lower: Vec<char> = "cat".chars().collect();
+        let upper: Vec<char> = "CAT".chars().collect();
+        let trie = TrieNode::from_sequences_normalized(&[&lower, &upper], |c| c.to_ascii_uppercase());
+
+        assert_eq!(trie.count_sequences(), 1);
+        assert_eq!(trie.frequency(&upper), 2);
+        assert!(!trie.contains(&lower));
+    }
+
+    #[test]
+    fn test_branching_nodes() {
+        let trie = TrieNode::from_words(&["cat", "car", "dog"]);
+        let mut branching = trie.branching_nodes();
+        branching.sort();
+        assert_eq!(branching, vec![vec![], vec!['c', 'a']]);
+    }
+
+    #[test]
+    fn test_sequence_iterator_adapters() {
+        let trie = TrieNode::from_words(&["a", "ab", "abc", "b"]);
+        let mut lengths: Vec<usize> = trie.iter_sequences()
+            .filter(|seq| seq.first() == Some(&'a'))
+            .map(|seq| seq.len())
+            .collect();
+        lengths.sort();
+        assert_eq!(lengths, vec![1, 2, 3]);
+        assert_eq!(trie.iter_sequences().len(), 4);
+
+        let mut long = trie.filter_sequences(|seq| seq.len() >= 2);
+        long.sort();
+        assert_eq!(long, vec![vec!['a', 'b'], vec!['a', 'b', 'c']]);
+    }
+
+    #[test]
+    fn test_single_variation_axis() {
+        let trie = TrieNode::from_trie_string("pre\n  cat*\n  cot*\n  cut*\nx\n  cat*\n  dog*\n").unwrap();
+        assert_eq!(trie.single_variation_axis(&['p', 'r', 'e']), Some(1));
+        assert_eq!(trie.single_variation_axis(&['x']), None);
+        assert_eq!(trie.single_variation_axis(&['z']), None);
+    }
+
+    #[test]
+    fn test_memory_footprint_grows() {
+        let mut trie = TrieNode::new();
+        let mut previous = trie.memory_footprint();
+        for word in ["cat", "car", "cart", "dog", "dot", "dove", "zebra"] {
+            trie.insert(&word.chars().collect::<Vec<_>>());
+            let current = trie.memory_footprint();
+            assert!(current >= previous);
+            previous = current;
+        }
+        assert!(previous > TrieNode::<char>::new().memory_footprint());
+    }
+
+    #[test]
+    fn test_contains_matching_with_sentinel() {
+        const WILD: char = '?';
+        let trie = TrieNode::from_words(&["cat", "cot", "cart"]);
+        assert!(trie.contains_matching(&['c', WILD, 't'], &WILD));
+        assert!(trie.contains_matching(&['c', 'o', WILD], &WILD));
+        assert!(!trie.contains_matching(&['c', WILD], &WILD));
+        assert!(!trie.contains_matching(&['d', WILD, 't'], &WILD));
+    }
+
+    #[test]
+    fn test_original_forms() {
+        let words: Vec<Vec<char>> = ["Cat", "CAT", "cat", "Cat"].iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = words.iter().map(|w| w.as_slice()).collect();
+        let normalized = TrieNode::from_sequences_normalized_with_originals(&sequences, |c| c.to_ascii_lowercase());
+
+        assert_eq!(normalized.trie().count_sequences(), 1);
+        let forms: Vec<String> = normalized.original_forms(&['c', 'a', 't']).into_iter().map(|f| f.into_iter().collect()).collect();
+        assert_eq!(forms, vec!["Cat", "CAT", "cat"]);
+        assert!(normalized.original_forms(&['d', 'o', 'g']).is_empty());
+    }
+
+    #[test]
+    fn test_query_with_repeat() {
+        let trie = TrieNode::from_words(&["a", "ab", "abc", "b"]);
+        let mut matches = trie.query(&[QueryElem::Exact('a'), QueryElem::AnyRepeat]);
+        matches.sort();
+        assert_eq!(matches, vec![vec!['a', 'b'], vec!['a', 'b', 'c']]);
+
+        let mut singles = trie.query(&[QueryElem::Any]);
+        singles.sort();
+        assert_eq!(singles, vec![vec!['a'], vec!['b']]);
+    }
+
+    #[test]
+    fn test_compact_merges_unary_chains() {
+        let mut trie = TrieNode::new();
+        for word in ["abcd", "abxy"] {
+            trie.insert(&word.chars().collect::<Vec<_>>());
+        }
+        trie.resegment(1);
+        let before = trie.stats().node_count;
+
+        trie.compact();
+        assert!(trie.stats().node_count < before);
+        assert_eq!(trie.stats().node_count, 4);
+        assert_eq!(trie.cached_stats().node_count, 4);
+        assert!(trie.contains(&['a', 'b', 'c', 'd']) && trie.contains(&['a', 'b', 'x', 'y']));
+        assert!(!trie.contains(&['a', 'b']));
+    }
+
+    #[test]
+    fn test_from_sequences_with_progress() {
+        let words: Vec<Vec<char>> = ["cat", "car", "cat", "dog", "do"].iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = words.iter().map(|w| w.as_slice()).collect();
+
+        let mut reports = Vec::new();
+        let trie = TrieNode::from_sequences_with_progress(&sequences, |done, total| reports.push((done, total)));
+
+        assert!(!reports.is_empty());
+        assert!(reports.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(reports.iter().all(|&(_, total)| total == 5));
+        assert_eq!(reports.last(), Some(&(5, 5)));
+        assert_eq!(trie, TrieNode::from_sequences(&sequences));
+    }
+
+    #[test]
+    fn test_length_group_ranges() {
+        let int_sequences = vec![
+            &[1, 2][..],
+            &[1, 3][..],
+            &[1, 2, 4, 5][..],
+            &[2, 3][..],
+            &[2, 3, 4][..],
+        ];
+        let grouped = LengthGroupedNode::from_trie(&TrieNode::from_sequences(&int_sequences));
+        assert_eq!(grouped.length_group_ranges(), vec![(1, 2), (1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn test_retain_by_sequence() {
+        let mut trie = TrieNode::from_words(&["a", "bb", "ccc", "dddd"]);
+        trie.retain(|seq| seq.len() % 2 == 0);
+
+        let mut kept: Vec<String> = trie.iter_sequences().map(|seq| seq.into_iter().collect()).collect();
+        kept.sort();
+        assert_eq!(kept, vec!["bb", "dddd"]);
+        assert_eq!(trie.stats().node_count, 3);
+        assert_eq!(trie.cached_stats().terminal_count, 2);
+    }
+
+    #[test]
+    fn test_to_dot_ranked() {
+        let trie = TrieNode::from_words(&["cat", "car", "dog"]);
+        let dot = trie.to_dot_ranked();
+
+        assert!(dot.starts_with("digraph trie {\n") && dot.ends_with("}\n"));
+        assert_eq!(dot.matches("rank=same").count(), 3);
+        assert!(dot.contains("{rank=same; n0;}"));
+        assert_eq!(dot.matches(" -> ").count(), trie.to_edges().1.len());
+        assert!(!trie.to_dot().contains("rank=same"));
+    }
+
+    #[test]
+    fn test_all_prefixes() {
+        let trie = TrieNode::from_words(&["ab", "abc"]);
+        let prefixes: Vec<String> = trie.all_prefixes().into_iter().map(|p| p.into_iter().collect()).collect();
+        assert_eq!(prefixes, vec!["", "ab", "abc"]);
+        assert!(TrieNode::<char>::new().all_prefixes().is_empty());
+    }
+
+    #[test]
+    fn test_cyclic_dag_traversal_errors() {
+        let dag = TrieNode::from_words(&["running", "jumping"]).factor_common_suffixes();
+        assert!(dag.check_acyclic().is_ok());
+        assert_eq!(dag.iter_sequences().unwrap().len(), 2);
+        assert_eq!(dag.stats().unwrap().terminal_count, 1);
+        assert!(dag.format_tree().unwrap().contains("i n g."));
+
+        // 0 -a-> 1 -b-> 2 -c-> 1
+        let cyclic = SuffixFactoredTrie::from_nodes(0, vec![
+            (false, vec![(vec!['a'], 1)]),
+            (true, vec![(vec!['b'], 2)]),
+            (false, vec![(vec!['c'], 1)]),
+        ]).unwrap();
+        assert_eq!(cyclic.check_acyclic(), Err(DagError::Cycle { node: 1 }));
+        assert!(cyclic.iter_sequences().is_err());
+        assert_eq!(cyclic.stats(), Err(DagError::Cycle { node: 1 }));
+        assert!(cyclic.format_tree().is_err());
+
+        let dangling = SuffixFactoredTrie::from_nodes(0, vec![(false, vec![(vec!['a'], 5)])]);
+        assert!(matches!(dangling, Err(DagError::DanglingEdge { node: 0, target: 5 })));
+
+        let rootless = SuffixFactoredTrie::<char>::from_nodes(2, vec![(true, Vec::new())]);
+        assert!(matches!(rootless, Err(DagError::MissingRoot { root: 2, len: 1 })));
+    }
+
+    #[test]
+    fn test_segmentation_savings() {
+        let trie = TrieNode::from_words(&["application", "applications", "applied", "apply"]);
+        let (segmented, per_element) = trie.segmentation_savings();
+        assert!(segmented < per_element);
+
+        let mut expanded = trie.clone();
+        expanded.resegment(1);
+        assert_eq!(expanded.stats().node_count, per_element);
+        assert_eq!(expanded.segmentation_savings(), (per_element, per_element));
+    }
+
+    #[test]
+    fn test_from_trie_conservative() {
+        let trie = TrieNode::from_trie_string("ab\n  x*\ncd\n  y*\nef\n  x*\n").unwrap();
+
+        let merged = LengthGroupedNode::from_trie(&trie);
+        assert_eq!(merged.num_children(), 1);
+
+        let conservative = LengthGroupedNode::from_trie_conservative(&trie);
+        assert_eq!(conservative.num_children(), 2);
+        let shared = LengthGroupKey::new(2, [vec!['a', 'b'], vec!['e', 'f']].into_iter().collect());
+        let distinct = LengthGroupKey::new(2, [vec!['c', 'd']].into_iter().collect());
+        assert!(conservative.children().contains_key(&shared));
+        assert!(conservative.children().contains_key(&distinct));
+    }
+
+    #[test]
+    fn test_trie_cursor() {
+        let mut trie = TrieNode::new();
+        for word in ["app", "apple", "application"] {
+            trie.insert(&word.chars().collect::<Vec<_>>());
+        }
+
+        let mut cursor = trie.cursor();
+        assert!(!cursor.is_terminal());
+        assert!(!cursor.descend(&['a']));
+        assert!(cursor.current_path().is_empty());
+
+        let steps = [("app", true), ("l", false), ("ication", true)];
+        for (segment, terminal) in steps {
+            let segment: Vec<char> = segment.chars().collect();
+            assert!(cursor.segments().contains(&segment.as_slice()));
+            assert!(cursor.descend(&segment));
+            assert_eq!(cursor.is_terminal(), terminal);
+        }
+        assert_eq!(cursor.current_path().into_iter().collect::<String>(), "application");
+        assert!(cursor.segments().is_empty());
+    }
+
+    #[test]
+    fn test_print_terminals_only() {
+        let words = ["dot", "cat", "car", "cart"];
+        let trie = TrieNode::from_words(&words);
+        let options = PrintOptions { separator: String::new(), terminals_only: true, ..PrintOptions::default() };
+
+        let output = trie.format_tree_with(&options);
+        let mut expected: Vec<&str> = words.to_vec();
+        expected.sort();
+        assert_eq!(output.lines().collect::<Vec<_>>(), expected);
+
+        assert_eq!(trie.format_tree_with(&PrintOptions::default()), trie.format_tree(" ", ".", false));
+    }
+
+    #[test]
+    fn test_partition_by_length() {
+        let trie = TrieNode::from_words(&["a", "abcd"]);
+        let (short, long) = trie.partition_by_length(3);
+
+        assert!(short.contains(&['a']) && !short.contains(&['a', 'b', 'c', 'd']));
+        assert!(long.contains(&['a', 'b', 'c', 'd']) && !long.contains(&['a']));
+        assert_eq!(short.count_sequences() + long.count_sequences(), trie.count_sequences());
+    }
+
+    #[test]
+    fn test_from_sequences_traced() {
+        let words: Vec<Vec<char>> = ["abc", "abd"].iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = words.iter().map(|w| w.as_slice()).collect();
+        let (trie, decisions) = TrieNode::from_sequences_traced(&sequences);
+
+        assert_eq!(trie, TrieNode::from_sequences(&sequences));
+        assert_eq!(
+            decisions[0],
+            SegmentDecision::CommonPrefix { position: 0, segment: vec!['a', 'b'], diverged: true }
+        );
+        assert!(decisions.contains(&SegmentDecision::Divergent { position: 0, segment: vec!['a', 'b'] }));
+    }
+
+    #[test]
+    fn test_trie_hash_is_order_independent() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::collections::HashSet;
+        use std::hash::{Hash, Hasher};
+
+        let words = ["cat", "car", "dog", "do", "cart"];
+        let mut forward = TrieNode::new();
+        for word in words {
+            forward.insert(&word.chars().collect::<Vec<_>>());
+        }
+        let mut backward = TrieNode::new();
+        for word in words.iter().rev() {
+            backward.insert(&word.chars().collect::<Vec<_>>());
+        }
+        assert_eq!(forward, backward);
+
+        let hash_of = |trie: &TrieNode<char>| {
+            let mut hasher = DefaultHasher::new();
+            trie.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash_of(&forward), hash_of(&backward));
+
+        let mut cache = HashSet::new();
+        cache.insert(forward);
+        assert!(cache.contains(&backward));
+    }
+
+    #[test]
+    fn test_build_incrementally() {
+        let words: Vec<Vec<char>> = ["cat", "car", "dog", "dot", "cart", "do"].iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = words.iter().map(|w| w.as_slice()).collect();
+
+        let snapshots: Vec<TrieNode<char>> = TrieNode::build_incrementally(&sequences).collect();
+        assert_eq!(snapshots.last(), Some(&TrieNode::from_sequences(&sequences)));
+        for pair in snapshots.windows(2) {
+            assert!(pair[0].count_sequences() < pair[1].count_sequences());
+            assert!(pair[0].iter_sequences().all(|seq| pair[1].contains(&seq)));
+        }
+        assert_eq!(TrieNode::<char>::build_incrementally(&[]).count(), 0);
+    }
+
+    #[test]
+    fn test_count_with_prefix() {
+        let trie = TrieNode::from_words(&["app", "apple", "application", "banana"]);
+        assert_eq!(trie.count_with_prefix(&['a', 'p', 'p']), 3);
+        assert_eq!(trie.count_with_prefix(&['a', 'p', 'p', 'l']), 2);
+        assert_eq!(trie.count_with_prefix(&['b', 'a']), 1);
+        assert_eq!(trie.count_with_prefix(&[]), 4);
+        assert_eq!(trie.count_with_prefix(&['c']), 0);
+    }
+
+    #[test]
+    fn test_grouped_print_end_marker() {
+        let mut trie = TrieNode::new();
+        trie.insert(&['a', 'b']);
+        trie.insert(&['a', 'b', 'c']);
+        let grouped = LengthGroupedNode::from_trie(&trie);
+
+        let plain = grouped.format_tree_with(&GroupedPrintOptions::default());
+        assert_eq!(plain, "Root\n└─len=2 'a' 'b'.\n  └─len=1 'c'.\n");
+
+        let options = GroupedPrintOptions { end_marker: true, ..GroupedPrintOptions::default() };
+        let marked = grouped.format_tree_with(&options);
+        assert_eq!(marked, "Root\n└─len=2 'a' 'b'\n  ├─ <end>\n  └─len=1 'c'\n    └─ <end>\n");
+    }
+
+    #[test]
+    fn test_intersect() {
+        let left = TrieNode::from_words(&["a", "b", "c"]);
+        let right = TrieNode::from_words(&["b", "c", "d"]);
+        let common = left.intersect(&right);
+
+        let mut sequences: Vec<Vec<char>> = common.iter_sequences().collect();
+        sequences.sort();
+        assert_eq!(sequences, vec![vec!['b'], vec!['c']]);
+        assert!(left.intersect(&TrieNode::new()).iter_sequences().next().is_none());
+    }
+
+    #[test]
+    fn test_union_leaves_inputs_untouched() {
+        let left = TrieNode::from_words(&["a", "b"]);
+        let right = TrieNode::from_words(&["b", "c"]);
+        let (left_before, right_before) = (left.clone(), right.clone());
+
+        let combined = left.union(&right);
+        assert!(combined.contains(&['a']) && combined.contains(&['b']) && combined.contains(&['c']));
+        assert_eq!(combined.count_sequences(), 3);
+        assert_eq!(combined.frequency(&['b']), 2);
+        assert_eq!(left, left_before);
+        assert_eq!(right, right_before);
+
+        // The result keeps the standard segmentation of its sequences
+        let trie = TrieNode::from_words(&["cat", "car", "dog"]);
+        assert_eq!(trie.union(&TrieNode::new()), trie);
+
+        // Inputs built with other options come back re-segmented
+        let words: Vec<Vec<char>> = ["ab1", "ab2", "xyz"].iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = words.iter().map(|w| w.as_slice()).collect();
+        let options = BuildOptions { min_common_prefix: 3, ..BuildOptions::default() };
+        let custom = TrieNode::from_sequences_with_options(&sequences, &options);
+        assert_eq!(custom.union(&TrieNode::new()), TrieNode::from_sequences(&sequences));
+    }
+
+    #[test]
+    fn test_difference() {
+        let all = TrieNode::from_words(&["a", "b", "c"]);
+        let remaining = all.difference(&TrieNode::from_words(&["b"]));
+
+        let mut sequences: Vec<Vec<char>> = remaining.iter_sequences().collect();
+        sequences.sort();
+        assert_eq!(sequences, vec![vec!['a'], vec!['c']]);
+
+        assert_eq!(all.difference(&TrieNode::from_words(&["x", "yz"])), all);
+    }
+
+    #[test]
+    fn test_grouped_flat_print_options() {
+        let mut trie = TrieNode::new();
+        trie.insert(&['a', 'b']);
+        let grouped = LengthGroupedNode::from_trie(&trie);
+
+        assert_eq!(grouped.format_flat(0, 2, "Terminal"), "(2, [['a', 'b']]):\n  Terminal\n");
+        assert_eq!(grouped.format_flat(1, 4, "END"), "    (2, [['a', 'b']]):\n        END\n");
+    }
+
+    #[test]
+    fn test_minimal_unique_prefixes() {
+        let trie = TrieNode::from_words(&["cat", "car", "dog", "do"]);
+        let prefixes = trie.minimal_unique_prefixes();
+        let lookup = |word: &str| -> String {
+            prefixes[&word.chars().collect::<Vec<_>>()].iter().collect()
+        };
+
+        assert_eq!(prefixes.len(), 4);
+        assert_eq!(lookup("cat"), "cat");
+        assert_eq!(lookup("car"), "car");
+        assert_eq!(lookup("dog"), "dog");
+        assert_eq!(lookup("do"), "do");
+
+        let trie = TrieNode::from_words(&["cat", "car", "dog"]);
+        assert_eq!(trie.minimal_unique_prefixes()[&vec!['d', 'o', 'g']], vec!['d']);
+    }
+
+    #[test]
+    fn test_build_with_metrics() {
+        let words: Vec<Vec<char>> = ["abc", "abd", "xyz"].iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = words.iter().map(|w| w.as_slice()).collect();
+        let (trie, metrics) = TrieNode::build_with_metrics(&sequences);
+
+        assert_eq!(trie, TrieNode::from_sequences(&sequences));
+        // Root, the `ab` group, then the `c`/`d` leaves
+        assert_eq!(metrics.max_recursion_depth, 3);
+        // Every edge comes from exactly one segment-producing decision
+        let produced = metrics.divergent + metrics.no_common_prefix + metrics.single_element
+            + (metrics.common_prefix - metrics.segment_splits);
+        assert_eq!(produced, trie.stats().node_count - 1);
+        assert!(metrics.segment_splits <= metrics.common_prefix);
+    }
+
+    #[test]
+    fn test_from_sequences_borrowed_does_not_clone() {
+        use std::fmt;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        struct Big(String);
+        impl Clone for Big {
+            fn clone(&self) -> Self {
+                CLONES.fetch_add(1, Ordering::SeqCst);
+                Big(self.0.clone())
+            }
+        }
+        impl fmt::Display for Big {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}", self.0)
+            }
+        }
+
+        let big = |s: &str| Big(s.repeat(1000));
+        let first = [big("a"), big("b"), big("c")];
+        let second = [big("a"), big("b"), big("d")];
+        let trie = TrieNode::from_sequences_borrowed(&[&first, &second]);
+
+        assert_eq!(CLONES.load(Ordering::SeqCst), 0);
+        assert!(trie.contains(&[&first[0], &first[1], &first[2]]));
+        assert_eq!(trie.count_sequences(), 2);
+    }
+
+    #[test]
+    fn test_sequences_through_segment() {
+        let mut trie = TrieNode::new();
+        for word in ["interact", "internal", "intern", "outer"] {
+            trie.insert(&word.chars().collect::<Vec<_>>());
+        }
+        let chunk: Vec<char> = "inter".chars().collect();
+
+        let mut words: Vec<String> = trie.sequences_through_segment(&[], &chunk)
+            .into_iter()
+            .map(|seq| seq.into_iter().collect())
+            .collect();
+        words.sort();
+        assert_eq!(words, vec!["interact", "intern", "internal"]);
+        assert!(trie.sequences_through_segment(&[], &['i', 'n']).is_empty());
+    }
+
+    #[test]
+    fn test_grouped_print_sort_order() {
+        let trie = TrieNode::from_trie_string("ab*\nc*\n").unwrap();
+        let grouped = LengthGroupedNode::from_trie(&trie);
+
+        let ascending = grouped.format_tree_with(&GroupedPrintOptions::default());
+        assert_eq!(ascending, "Root\n├─len=1 'c'.\n└─len=2 'a' 'b'.\n");
+
+        let options = GroupedPrintOptions { sort: SortOrder::LengthDesc, ..GroupedPrintOptions::default() };
+        let descending = grouped.format_tree_with(&options);
+        assert_eq!(descending, "Root\n├─len=2 'a' 'b'.\n└─len=1 'c'.\n");
+    }
+
+    #[test]
+    fn test_length_signatures() {
+        let words = ["ape", "app", "application", "bans", "bat", "banner", "pot", "potion"];
+        let grouped = LengthGroupedNode::from_trie(&TrieNode::from_words(&words));
+
+        // ap|e, ap|p, ba|t -> [2, 1]; ba|n|s -> [2, 1, 1]; ba|n|ner -> [2, 1, 3];
+        // ap|p|lication -> [2, 1, 8]; pot -> [3]; pot|ion -> [3, 3]
+        assert_eq!(
+            grouped.length_signatures(),
+            vec![vec![2, 1], vec![2, 1, 1], vec![2, 1, 3], vec![2, 1, 8], vec![3], vec![3, 3]]
+        );
+    }
+
+    #[test]
+    fn test_from_words_with_empty_word() {
+        let trie = TrieNode::from_words(&["", "a"]);
+        assert!(trie.is_terminal());
+        assert_eq!(trie.num_children(), 1);
+        assert!(trie.contains(&[]) && trie.contains(&['a']));
+        assert_eq!(trie.frequency(&[]), 1);
+
+        let only_empty = TrieNode::from_words(&["", ""]);
+        assert!(only_empty.is_terminal());
+        assert_eq!(only_empty.num_children(), 0);
+        assert_eq!(only_empty.frequency(&[]), 2);
+    }
+
+    #[test]
+    fn test_compression_report() {
+        let words = vec!["ape", "app", "application", "bans", "bat", "banner", "pot", "potion"];
+        let grouped = LengthGroupedNode::from_trie(&TrieNode::from_words(&words));
+        let report = grouped.compression_report();
+
+        assert_eq!(report.group_count, grouped.children().len());
+        assert_eq!(report.collapsed_children, vec![(2, 2), (3, 1)]);
+        assert_eq!(report.average_segments_per_group, 1.5);
+    }
+
+    #[test]
+    fn test_from_patterns() {
+        let pattern = vec![PatternElement::Exact('c'), PatternElement::Any, PatternElement::Exact('t')];
+        let trie = TrieNode::from_patterns(std::slice::from_ref(&pattern), &['a', 'o', 'u'], 100).unwrap();
+
+        let mut words: Vec<String> = trie.iter_sequences().map(|seq| seq.into_iter().collect()).collect();
+        words.sort();
+        assert_eq!(words, vec!["cat", "cot", "cut"]);
+
+        assert_eq!(
+            TrieNode::from_patterns(std::slice::from_ref(&pattern), &['a', 'o', 'u'], 2),
+            Err(BuildError::TooManyExpansions { limit: 2 })
+        );
+
+        // A wildcard has nothing to expand to, so it is rejected rather than dropped
+        assert_eq!(
+            TrieNode::from_patterns(&[pattern], &[], 100),
+            Err(BuildError::EmptyAlphabet)
+        );
+        let exact = vec![PatternElement::Exact('o'), PatternElement::Exact('k')];
+        assert!(TrieNode::from_patterns(&[exact], &[], 100).unwrap().contains(&['o', 'k']));
+    }
+
+    #[test]
+    fn test_concurrent_readers_and_writer() {
+        let shared = ConcurrentTrie::new(TrieNode::from_words(&["cat", "car", "dog"]));
+        let car: Vec<char> = "car".chars().collect();
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let trie = shared.clone();
+                let car = car.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        assert!(trie.contains(&car));
+                        assert!(trie.completions(&['c']).len() >= 2);
+                    }
+                })
+            })
+            .collect();
+        let writer = {
+            let trie = shared.clone();
+            std::thread::spawn(move || {
+                for word in ["cab", "cart", "dot"] {
+                    trie.insert(&word.chars().collect::<Vec<_>>());
+                }
+            })
+        };
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        let mut words: Vec<String> = shared.completions(&['c', 'a'])
+            .into_iter()
+            .map(|seq| seq.into_iter().collect())
+            .collect();
+        words.sort();
+        assert_eq!(words, vec!["cab", "car", "cart", "cat"]);
+        assert!(shared.contains(&['d', 'o', 't']));
+    }
+
+    #[test]
+    fn test_to_newick() {
+        let newick = TrieNode::from_words(&["cat", "car", "dog"]).to_newick("");
+        assert_eq!(newick, "((car[r],cat[t])[ca],dog[dog]);");
+        assert!(newick.ends_with(';'));
+        assert_eq!(newick.matches('(').count(), newick.matches(')').count());
+
+        // Reserved characters are quoted; a terminal inner node becomes a leaf
+        let reserved = TrieNode::from_words(&["a", "a,b", "a;c"]).to_newick("");
+        assert_eq!(reserved, "((a,'a,b'[',b'],'a;c'[';c'])[a]);");
+    }
+
+    #[test]
+    fn test_jaccard() {
+        let abc = TrieNode::from_words(&["a", "b", "c"]);
+        let bcd = TrieNode::from_words(&["b", "c", "d"]);
+        let xyz = TrieNode::from_words(&["x", "y", "z"]);
+
+        assert_eq!(abc.jaccard(&abc.clone()), 1.0);
+        assert_eq!(abc.jaccard(&xyz), 0.0);
+        assert_eq!(abc.jaccard(&bcd), 2.0 / 4.0);
+        assert_eq!(TrieNode::<char>::new().jaccard(&TrieNode::new()), 1.0);
+    }
+
+    #[test]
+    fn test_from_trie_to_depth() {
+        let words = vec!["ape", "app", "application", "bans", "bat", "banner", "pot", "potion"];
+        let trie = TrieNode::from_words(&words);
+        let full = LengthGroupedNode::from_trie(&trie);
+        assert!(full.length_group_ranges().len() > 1);
+
+        let capped = LengthGroupedNode::from_trie_to_depth(&trie, 1);
+        assert_eq!(capped.length_group_ranges().len(), 1);
+        assert_eq!(capped.num_children(), full.num_children());
+        assert!(capped.children().values().all(|child| child.num_children() == 0));
+
+        assert_eq!(LengthGroupedNode::from_trie_to_depth(&trie, usize::MAX), full);
+    }
+
+    #[test]
+    fn test_all_group_keys() {
+        fn key(length: usize, segments: &[&str]) -> LengthGroupKey<char> {
+            LengthGroupKey::new(length, segments.iter().map(|s| s.chars().collect()).collect())
+        }
+        let words = vec!["ape", "app", "application", "bans", "bat", "banner", "pot", "potion"];
+        let grouped = LengthGroupedNode::from_trie(&TrieNode::from_words(&words));
+        let keys = grouped.all_group_keys();
+
+        assert!(keys.contains(&key(2, &["ap", "ba"])));
+        assert!(keys.contains(&key(3, &["pot"])));
+        assert!(keys.contains(&key(3, &["ner"])));
+        assert_eq!(keys.len(), 7);
+        // Ordered by length first
+        assert_eq!(keys.iter().next(), Some(&key(1, &["e", "n", "p", "t"])));
+    }
+
+    #[test]
+    fn test_from_sorted_sequences_matches_from_sequences() {
+        let mut words = vec!["ape", "app", "application", "bans", "bat", "banner", "pot", "potion", "", "p"];
+        words.sort();
+        let chars: Vec<Vec<char>> = words.iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = chars.iter().map(|seq| seq.as_slice()).collect();
+
+        assert_eq!(TrieNode::from_sorted_sequences(&sequences), TrieNode::from_sequences(&sequences));
+
+        let mut ints: Vec<Vec<u32>> = (0..200u32).map(|i| vec![i % 3, i % 7, i % 5, i % 11]).collect();
+        ints.sort();
+        let sequences: Vec<&[u32]> = ints.iter().map(|seq| seq.as_slice()).collect();
+        assert_eq!(TrieNode::from_sorted_sequences(&sequences), TrieNode::from_sequences(&sequences));
+    }
+
+    #[test]
+    fn test_labeled_sequences() {
+        let cat: Vec<char> = "cat".chars().collect();
+        let car: Vec<char> = "car".chars().collect();
+        let labeled = TrieNode::from_labeled_sequences(&[
+            (&cat[..], "doc-1"),
+            (&car[..], "doc-2"),
+            (&cat[..], "doc-3"),
+        ]);
+
+        assert_eq!(labeled.labels_of(&cat), &["doc-1", "doc-3"]);
+        assert_eq!(labeled.labels_of(&car), &["doc-2"]);
+        assert!(labeled.labels_of(&['c', 'a']).is_empty());
+        assert_eq!(labeled.trie().frequency(&cat), 2);
+    }
+
+    #[test]
+    fn test_print_show_ids_match_edges() {
+        let trie = TrieNode::from_words(&["cat", "car", "cart", "dog", "do"]);
+        let options = PrintOptions {
+            separator: String::new(),
+            terminal_char: String::new(),
+            show_ids: true,
+            ..PrintOptions::default()
+        };
+        let printed = trie.format_tree_with(&options);
+        assert!(printed.starts_with("0 Root"));
+
+        let mut from_print: Vec<(usize, String)> = printed.lines()
+            .skip(1)
+            .map(|line| {
+                let (id, rest) = line.trim_start().split_once(' ').unwrap();
+                let segment = rest.rsplit("─ ").next().unwrap();
+                (id.parse().unwrap(), segment.to_string())
+            })
+            .collect();
+        from_print.sort();
+
+        let (_, edges) = trie.to_edges();
+        let mut from_edges: Vec<(usize, String)> = edges.into_iter()
+            .map(|(_, child, segment)| (child, segment.into_iter().collect()))
+            .collect();
+        from_edges.sort();
+        assert_eq!(from_print, from_edges);
+    }
+
+    #[test]
+    fn test_canonicalize_shares_repeated_subtrees() {
+        let trie = TrieNode::from_words(&["catx", "caty", "dogsx", "dogsy"]);
+        let grouped = LengthGroupedNode::from_trie(&trie);
+        let canonical = grouped.canonicalize();
+
+        // Both `[x, y]` tails and their terminal leaves are stored once
+        assert_eq!(grouped.node_count(), 5);
+        assert_eq!(canonical.node_count(), 3);
+        assert!(canonical.node_count() < grouped.node_count());
+
+        for word in ["catx", "caty", "dogsx", "dogsy"] {
+            assert!(canonical.contains(&word.chars().collect::<Vec<_>>()));
+        }
+        assert!(!canonical.contains(&['c', 'a', 't']));
+    }
+
+    #[test]
+    fn test_take_subtrie() {
+        let mut trie = TrieNode::new();
+        for word in ["app", "apple", "apply", "bat"] {
+            trie.insert(&word.chars().collect::<Vec<_>>());
+        }
+        let chars = |word: &str| word.chars().collect::<Vec<_>>();
+
+        let branch = trie.take_subtrie(&chars("app")).unwrap();
+        assert!(branch.contains(&[]) && branch.contains(&chars("le")) && branch.contains(&chars("ly")));
+        assert!(!trie.contains(&chars("app")) && !trie.contains(&chars("apple")));
+        assert!(trie.contains(&chars("bat")));
+        assert_eq!(trie.cached_stats().terminal_count, 1);
+
+        trie.replace_subtrie(&chars("bat"), branch).unwrap();
+        assert!(trie.contains(&chars("bat")) && trie.contains(&chars("batle")) && trie.contains(&chars("batly")));
+
+        assert_eq!(trie.take_subtrie(&chars("ba")), None);
+        assert_eq!(trie.take_subtrie(&chars("zzz")), None);
+    }
+
+    #[test]
+    fn test_to_dot_with_custom_labels() {
+        let sequences = vec![&[5u8, 2][..], &[5u8, 3][..]];
+        let trie = TrieNode::from_sequences(&sequences);
+        let dot = trie.to_dot_with(|n: &u8| format!("\"{:b}\"", n));
+
+        assert!(dot.contains(r#"[label="\"101\""]"#));
+        assert!(dot.contains(r#"[label="\"10\""]"#));
+        assert!(dot.contains(r#"[label="\"11\""]"#));
+        assert!(!dot.contains(r#"label="5""#));
+    }
+
+    #[test]
+    fn test_average_sequence_length() {
+        assert_eq!(TrieNode::from_words(&["a", "abc", "abcde"]).average_sequence_length(), 3.0);
+        assert_eq!(TrieNode::<char>::new().average_sequence_length(), 0.0);
+    }
+
+    #[test]
+    fn test_length_group_key_try_new() {
+        let segments = |words: &[&str]| -> std::collections::BTreeSet<Vec<char>> {
+            words.iter().map(|w| w.chars().collect()).collect()
+        };
+        assert_eq!(
+            LengthGroupKey::try_new(2, segments(&["ab", "cd"])),
+            Ok(LengthGroupKey::new(2, segments(&["ab", "cd"])))
+        );
+        assert_eq!(
+            LengthGroupKey::try_new(2, segments(&["abc"])),
+            Err(KeyError::LengthMismatch { length: 2, found: vec![3] })
+        );
+    }
+
+    #[test]
+    fn test_duplicate_full_matches_end_in_one_terminal() {
+        let sequences = vec![&['a', 'b'][..], &['a', 'b'][..]];
+        let trie = TrieNode::from_sequences(&sequences);
+
+        let (nodes, edges) = trie.to_edges();
+        assert_eq!(edges, vec![(0, 1, vec!['a', 'b'])]);
+        assert!(nodes[1].is_terminal);
+        assert_eq!(trie.frequency(&['a', 'b']), 2);
+        assert_eq!(trie.cached_stats().node_count, 2);
+
+        // The leaf still counts against the node limit
+        let options = BuildOptions { max_nodes: 1, ..BuildOptions::default() };
+        assert_eq!(
+            TrieNode::try_from_sequences_with_options(&sequences, &options),
+            Err(BuildError::TooLarge { max_nodes: 1 })
+        );
+    }
+
+    #[test]
+    fn test_extract_patterns_without_stored_sequences() {
+        let trie = TrieNode::from_trie_string("a\nb\n").unwrap();
+        assert!(trie.extract_patterns().is_empty());
+
+        let mut trie = TrieNode::from_words(&["log1", "log2"]);
+        trie.replace_subtrie(&[], TrieNode::from_trie_string("x\ny\n").unwrap()).unwrap();
+        assert!(trie.extract_patterns().is_empty());
+    }
+
+    #[test]
+    fn test_validate_well_formed() {
+        let trie = TrieNode::from_words(&["ape", "app", "application", "bans", "bat"]);
+        let grouped = LengthGroupedNode::from_trie(&trie);
+        assert!(grouped.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_malformed_keys() {
+        use std::collections::HashMap;
+        fn key(length: usize, segments: &[&str]) -> LengthGroupKey<char> {
+            LengthGroupKey::new(length, segments.iter().map(|s| s.chars().collect()).collect())
+        }
+        let leaf = || LengthGroupedNode::with_children(true, HashMap::new());
+
+        let mixed = LengthGroupedNode::with_children(false, HashMap::from([(key(2, &["ab", "abc"]), leaf())]));
+        assert_eq!(
+            mixed.validate(),
+            Err(KeyError::LengthMismatch { length: 2, found: vec![2, 3] })
+        );
+
+        let declared = LengthGroupedNode::with_children(false, HashMap::from([(key(5, &["ab", "cd"]), leaf())]));
+        assert_eq!(declared.validate(), Err(KeyError::LengthMismatch { length: 5, found: vec![2] }));
+
+        // (5, {ab}) is reported even though its sibling (2, {ab}) is well formed
+        let colliding = LengthGroupedNode::with_children(false, HashMap::from([
+            (key(5, &["ab"]), LengthGroupedNode::with_children(false, HashMap::from([(key(1, &["x"]), leaf())]))),
+            (key(2, &["ab"]), leaf()),
+        ]));
+        assert_eq!(colliding.validate(), Err(KeyError::LengthMismatch { length: 5, found: vec![2] }));
+    }
+
+    #[test]
+    fn test_min_common_prefix_applies_to_grouped_segments() {
+        let words: Vec<Vec<char>> = ["ab1", "ab2", "xyz"].iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = words.iter().map(|w| w.as_slice()).collect();
+        let options = BuildOptions { min_common_prefix: 3, ..BuildOptions::default() };
+        let trie = TrieNode::from_sequences_with_options(&sequences, &options);
+
+        let (_, edges) = trie.to_edges();
+        let mut segments: Vec<Vec<char>> = edges.into_iter().map(|(_, _, segment)| segment).collect();
+        segments.sort();
+        assert_eq!(segments, vec![vec!['1'], vec!['2'], vec!['a'], vec!['b'], vec!['x', 'y', 'z']]);
+        for seq in &sequences {
+            assert!(trie.contains(seq));
+        }
+    }
+
+    #[test]
+    fn test_cached_stats_on_subtries() {
+        let trie = TrieNode::from_words(&["cat", "car", "dog"]);
+        let subtrie = trie.get_subtrie(&['c', 'a']).unwrap();
+        let cached = subtrie.cached_stats();
+        assert_eq!((cached.node_count, cached.terminal_count), (3, 2));
+
+        let mut trie = TrieNode::from_words(&["cat", "car"]);
+        trie.insert(&['c', 'o', 'w']);
+        trie.resegment(1);
+        trie.set_terminal(true);
+        let check = |node: &TrieNode<char>| {
+            let full = node.stats();
+            assert_eq!((node.cached_stats().node_count, node.cached_stats().terminal_count), (full.node_count, full.terminal_count));
+        };
+        check(&trie);
+        check(trie.get_subtrie(&['c']).unwrap());
+        check(trie.get_subtrie(&['c', 'a']).unwrap());
+    }
+
+    #[test]
+    fn test_remove_with_overlapping_siblings() {
+        // A structural merge of differently segmented tries leaves the
+        // overlapping siblings `ab` and `a` under the root
+        let mut trie = TrieNode::from_words(&["abc", "abd"]);
+        trie.merge(&TrieNode::from_words(&["abx", "ay"]));
+
+        assert!(trie.contains(&['a', 'b', 'x']));
+        assert!(trie.remove(&['a', 'b', 'x']));
+        assert!(!trie.contains(&['a', 'b', 'x']));
+        assert!(trie.contains(&['a', 'b', 'c']) && trie.contains(&['a', 'y']));
+        assert_eq!(trie.cached_stats().terminal_count, 3);
+        assert_eq!(trie.cached_stats().node_count, trie.stats().node_count);
+    }
+
+    #[test]
+    fn test_build_metrics_for_duplicate_full_matches() {
+        let sequences = vec![&['a', 'b'][..], &['a', 'b'][..]];
+        let (trie, metrics) = TrieNode::build_with_metrics(&sequences);
+
+        // The terminal leaf is built directly, without a second builder call
+        assert_eq!(metrics.max_recursion_depth, 1);
+        assert_eq!(trie.stats().max_depth, 1);
+        assert_eq!(metrics.common_prefix, 1);
+        assert_eq!(metrics.segment_splits, 0);
+    }
+
+    #[test]
+    fn test_try_from_sequences_on_jagged_input() {
+        let jagged = vec![&['a'][..], &['a', 'b', 'c'][..], &[][..], &['x', 'y'][..], &['a', 'b'][..]];
+        let trie = TrieNode::try_from_sequences(&jagged).unwrap();
+
+        assert_eq!(trie, TrieNode::from_sequences(&jagged));
+        for seq in &jagged {
+            assert!(trie.contains(seq));
+        }
+        assert_eq!(trie.count_sequences(), jagged.len());
+
+        // Failures surface as a `BuildError` rather than a panic
+        let options = BuildOptions { max_nodes: 2, ..BuildOptions::default() };
+        assert_eq!(
+            TrieNode::try_from_sequences_with_options(&jagged, &options),
+            Err(BuildError::TooLarge { max_nodes: 2 })
+        );
+    }
+
+    #[test]
+    fn test_trie_string_round_trip_after_set_terminal() {
+        let mut trie = TrieNode::from_words(&["ab"]);
+        trie.set_terminal(true);
+        assert_eq!(trie.frequency(&[]), 1);
+        assert_eq!(TrieNode::from_trie_string(&trie.to_trie_string()).unwrap(), trie);
+    }
+
+    #[test]
+    fn test_retain_keeping_everything_is_a_no_op() {
+        let words: Vec<Vec<char>> = ["ab1", "ab2", "xyz"].iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = words.iter().map(|w| w.as_slice()).collect();
+        let options = BuildOptions { min_common_prefix: 3, ..BuildOptions::default() };
+        let original = TrieNode::from_sequences_with_options(&sequences, &options);
+
+        // The `a` -> `b` chain is not joined when nothing was removed
+        let mut trie = original.clone();
+        trie.retain(|_| true);
+        assert_eq!(trie, original);
+    }
+
+    #[test]
+    fn test_retain_merges_rejoined_segments_into_siblings() {
+        // Plain `merge` leaves overlapping siblings `ab` and `abc`
+        let mut trie = TrieNode::from_words(&["abc", "abd"]);
+        trie.merge(&TrieNode::from_words(&["abc", "abcz"]));
+        trie.retain(|seq| seq != ['a', 'b', 'd']);
+
+        // Re-joining `ab` + `c` lands on the existing `abc` sibling
+        assert!(!trie.contains(&['a', 'b', 'd']));
+        assert!(trie.contains(&['a', 'b', 'c', 'z']));
+        assert_eq!(trie.frequency(&['a', 'b', 'c']), 2);
+        assert_eq!(trie.count_sequences(), 2);
+    }
+
+    #[test]
+    fn test_retain_by_total_length_keeping_everything_is_a_no_op() {
+        let words: Vec<Vec<char>> = ["ab1", "ab2", "xyz"].iter().map(|w| w.chars().collect()).collect();
+        let sequences: Vec<&[char]> = words.iter().map(|w| w.as_slice()).collect();
+        let options = BuildOptions { min_common_prefix: 3, ..BuildOptions::default() };
+        let original = TrieNode::from_sequences_with_options(&sequences, &options);
+
+        let mut trie = original.clone();
+        trie.retain_by_total_length(|n| n >= 3);
+        assert_eq!(trie, original);
+
+        // Overlapping siblings from `merge` are merged, not overwritten
+        let mut trie = TrieNode::from_words(&["abc", "abdef"]);
+        trie.merge(&TrieNode::from_words(&["abc", "abcz"]));
+        trie.retain_by_total_length(|n| n < 5);
+        assert!(trie.contains(&['a', 'b', 'c', 'z']));
+        assert_eq!(trie.frequency(&['a', 'b', 'c']), 2);
+        assert_eq!(trie.count_sequences(), 2);
+    }
+}